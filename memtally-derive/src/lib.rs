@@ -0,0 +1,84 @@
+//! Derive macro for `memtally::HeapTree`. Use it through the `derive` feature
+//! of `memtally`.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Index, parse_macro_input, spanned::Spanned};
+
+/// Implements `HeapTree` for a struct, with one child per field.
+///
+/// Fields must implement `HeapTree` themselves, unless they are marked with
+/// `#[heap_tree(heap_size)]`, which makes them a leaf sized by `HeapSize`,
+/// or `#[heap_tree(skip)]`.
+#[proc_macro_derive(HeapTree, attributes(heap_tree))]
+pub fn derive_heap_tree(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum Kind {
+    Tree,
+    HeapSize,
+    Skip,
+}
+
+fn field_kind(field: &syn::Field) -> syn::Result<Kind> {
+    let mut kind = Kind::Tree;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("heap_tree") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("heap_size") {
+                kind = Kind::HeapSize;
+                Ok(())
+            } else if meta.path.is_ident("skip") {
+                kind = Kind::Skip;
+                Ok(())
+            } else {
+                Err(meta.error("expected `heap_size` or `skip`"))
+            }
+        })?;
+    }
+    Ok(kind)
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(
+            input.span(),
+            "HeapTree can only be derived for structs",
+        ));
+    };
+    let mut children = Vec::new();
+    for (i, field) in data.fields.iter().enumerate() {
+        let (member, name) = match &field.ident {
+            Some(ident) => (quote!(#ident), ident.to_string()),
+            None => {
+                let index = Index::from(i);
+                (quote!(#index), i.to_string())
+            }
+        };
+        children.push(match field_kind(field)? {
+            Kind::Tree => quote! {
+                ::memtally::HeapTree::heap_tree(&self.#member, #name)
+            },
+            Kind::HeapSize => quote! {
+                ::memtally::MemoryTree::leaf(#name, ::memtally::HeapSize::heap_size(&self.#member))
+            },
+            Kind::Skip => continue,
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::memtally::HeapTree for #ident #ty_generics #where_clause {
+            fn heap_tree(&self, name: &str) -> ::memtally::MemoryTree {
+                ::memtally::MemoryTree::node(name, ::std::vec![#(#children),*])
+            }
+        }
+    })
+}
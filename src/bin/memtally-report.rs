@@ -0,0 +1,222 @@
+//! Prints reports of snapshots written by `MemorySnapshot::to_json`.
+
+use std::{env, fmt::Write, fs, process::ExitCode};
+
+use memtally::{Change, MemorySnapshot, SnapshotDiff, registry::Report};
+
+const USAGE: &str = "\
+Usage: memtally-report [OPTIONS] <SNAPSHOT> [<LATER>]
+
+Prints the largest collections of SNAPSHOT, or with LATER, the collections
+that changed the most between the two snapshots. Snapshots are JSON files
+as written by `MemorySnapshot::to_json`, or `-` for stdin.
+
+Options:
+  -n, --top <N>      Show at most N collections [default: 20]
+  -s, --sort <KEY>   Order by total, shallow or indirect bytes, or by their
+                     change with LATER [default: total]
+      --folded       Print folded stacks of SNAPSHOT for flamegraph tools
+  -h, --help         Print this help";
+
+#[derive(Clone, Copy)]
+enum SortKey {
+    Total,
+    Shallow,
+    Indirect,
+}
+
+impl SortKey {
+    fn of(self, report: &Report) -> usize {
+        match self {
+            SortKey::Total => report.total(),
+            SortKey::Shallow => report.shallow,
+            SortKey::Indirect => report.indirect,
+        }
+    }
+
+    /// How much the key changed, in either direction.
+    fn change(self, change: &Change) -> usize {
+        let of = |report: Option<Report>| report.map_or(0, |report| self.of(&report));
+        of(change.after).abs_diff(of(change.before))
+    }
+}
+
+enum Command {
+    Help,
+    Report(Args),
+}
+
+struct Args {
+    top: usize,
+    sort: SortKey,
+    folded: bool,
+    files: Vec<String>,
+}
+
+fn parse_args(mut iter: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut args = Args {
+        top: 20,
+        sort: SortKey::Total,
+        folded: false,
+        files: Vec::new(),
+    };
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-n" | "--top" => {
+                let n = iter.next().ok_or("--top needs a value")?;
+                args.top = n.parse().map_err(|_| format!("invalid --top: {n}"))?;
+            }
+            "-s" | "--sort" => {
+                args.sort = match iter.next().as_deref() {
+                    Some("total") => SortKey::Total,
+                    Some("shallow") => SortKey::Shallow,
+                    Some("indirect") => SortKey::Indirect,
+                    Some(key) => return Err(format!("invalid --sort: {key}")),
+                    None => return Err("--sort needs a value".to_owned()),
+                };
+            }
+            "--folded" => args.folded = true,
+            option if option.starts_with('-') && option != "-" => {
+                return Err(format!("unknown option: {option}"));
+            }
+            _ => args.files.push(arg),
+        }
+    }
+    match args.files.len() {
+        1 => Ok(Command::Report(args)),
+        2 if args.folded => Err("--folded takes a single snapshot".to_owned()),
+        2 => Ok(Command::Report(args)),
+        _ => Err("expected one or two snapshot files".to_owned()),
+    }
+}
+
+fn load(path: &str) -> Result<MemorySnapshot, String> {
+    let json = if path == "-" {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        fs::read_to_string(path)
+    }
+    .map_err(|e| format!("cannot read {path}: {e}"))?;
+    MemorySnapshot::from_json(&json).map_err(|e| format!("cannot parse {path}: {e}"))
+}
+
+fn top(snapshot: &MemorySnapshot, args: &Args) -> String {
+    let mut collections: Vec<_> = snapshot.collections().iter().collect();
+    collections.sort_by_key(|entry| std::cmp::Reverse(args.sort.of(&entry.report)));
+    let shown = &collections[..args.top.min(collections.len())];
+
+    let mut rows: Vec<(String, Report)> = shown
+        .iter()
+        .map(|entry| (entry.name.clone(), entry.report))
+        .collect();
+    let hidden = collections.len() - shown.len();
+    if hidden > 0 {
+        let rest = collections[shown.len()..]
+            .iter()
+            .fold(Report::default(), |sum, entry| Report {
+                shallow: sum.shallow + entry.report.shallow,
+                indirect: sum.indirect + entry.report.indirect,
+            });
+        rows.push((format!("({hidden} more)"), rest));
+    }
+
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(["collection".len()])
+        .max()
+        .unwrap_or_default();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:width$}  {:>12}  {:>12}  {:>12}",
+        "collection", "shallow", "indirect", "total"
+    )
+    .unwrap();
+    for (name, report) in &rows {
+        let Report { shallow, indirect } = report;
+        let total = report.total();
+        writeln!(
+            out,
+            "{name:width$}  {shallow:>12}  {indirect:>12}  {total:>12}"
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn diff(before: &MemorySnapshot, after: &MemorySnapshot, args: &Args) -> String {
+    let mut diff = before.diff(after);
+    let delta = diff.delta();
+    if !matches!(args.sort, SortKey::Total) {
+        diff.changes.sort_by(|a, b| {
+            args.sort
+                .change(b)
+                .cmp(&args.sort.change(a))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+    }
+    let hidden = diff.changes.len().saturating_sub(args.top);
+    diff.changes.truncate(args.top);
+    let mut out = SnapshotDiff::to_string(&diff);
+    if hidden > 0 {
+        writeln!(out, "({hidden} more changes)").unwrap();
+    }
+    writeln!(
+        out,
+        "total: {} -> {} bytes ({delta:+})",
+        before.total(),
+        after.total()
+    )
+    .unwrap();
+    out
+}
+
+/// One line per collection and kind of memory, as `name;kind bytes`.
+fn folded(snapshot: &MemorySnapshot) -> String {
+    let mut out = String::new();
+    for entry in snapshot.collections() {
+        // Semicolons separate frames in the folded format.
+        let name = entry.name.replace(';', "_");
+        for (kind, bytes) in [
+            ("shallow", entry.report.shallow),
+            ("indirect", entry.report.indirect),
+        ] {
+            if bytes > 0 {
+                writeln!(out, "{name};{kind} {bytes}").unwrap();
+            }
+        }
+    }
+    out
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Command::Help) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Report(args)) => args,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let snapshots: Result<Vec<_>, _> = args.files.iter().map(|path| load(path)).collect();
+    let snapshots = match snapshots {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match &snapshots[..] {
+        [snapshot] if args.folded => folded(snapshot),
+        [snapshot] => top(snapshot, &args),
+        [before, after] => diff(before, after, &args),
+        _ => unreachable!("parse_args checks the number of files"),
+    };
+    print!("{report}");
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+use metrics::Label;
+
+use crate::{ShallowHeapSize, Tracked};
+
+/// A `metrics` gauge that follows the total heap usage of a collection.
+#[derive(Debug)]
+pub(crate) struct Gauge {
+    handle: metrics::Gauge,
+    min_interval: Duration,
+    last_published: Option<(Instant, usize)>,
+    /// Shallow heap usage as of the last operation.
+    shallow: usize,
+}
+
+impl Gauge {
+    pub(crate) fn shallow(&self) -> usize {
+        self.shallow
+    }
+
+    pub(crate) fn observe(&mut self, shallow: usize, indirect: usize) {
+        self.shallow = shallow;
+        let total = shallow + indirect;
+        match self.last_published {
+            Some((_, published)) if published == total => {}
+            Some((at, _)) if !self.min_interval.is_zero() && at.elapsed() < self.min_interval => {}
+            _ => self.publish(total),
+        }
+    }
+
+    #[allow(
+        clippy::cast_precision_loss,
+        reason = "Gauges are floats, and sizes beyond 2^53 bytes do not occur"
+    )]
+    fn publish(&mut self, total: usize) {
+        self.handle.set(total as f64);
+        self.last_published = Some((Instant::now(), total));
+    }
+}
+
+impl<C: ShallowHeapSize> Tracked<C> {
+    /// Publish the total heap usage through the `metrics` gauge `name` with
+    /// `labels`, once now and then after every operation that changes it.
+    /// Replaces any gauge set before.
+    ///
+    /// The gauge is registered with the recorder that is installed when this
+    /// is called.
+    pub fn publish_gauge(&mut self, name: impl Into<String>, labels: &[(&str, &str)]) {
+        self.publish_gauge_every(name, labels, Duration::ZERO);
+    }
+
+    /// Like [`Tracked::publish_gauge`], but publishes at most once per
+    /// `min_interval`. Changes within the interval are only published by a
+    /// later operation, or by [`Tracked::flush_gauge`].
+    pub fn publish_gauge_every(
+        &mut self,
+        name: impl Into<String>,
+        labels: &[(&str, &str)],
+        min_interval: Duration,
+    ) {
+        let labels: Vec<Label> = labels
+            .iter()
+            .map(|&(key, value)| Label::new(key.to_owned(), value.to_owned()))
+            .collect();
+        let mut gauge = Gauge {
+            handle: metrics::gauge!(name.into(), labels),
+            min_interval,
+            last_published: None,
+            shallow: 0,
+        };
+        gauge.observe(self.inner.shallow_heap_size(), self.tally.indirect());
+        self.tally.set_gauge(Some(gauge));
+    }
+
+    /// Publishes the current total heap usage right away, regardless of the
+    /// interval.
+    pub fn flush_gauge(&mut self) {
+        let shallow = self.inner.shallow_heap_size();
+        let indirect = self.tally.indirect();
+        if let Some(gauge) = self.tally.gauge_mut() {
+            gauge.shallow = shallow;
+            gauge.publish(shallow + indirect);
+        }
+    }
+}
+
+impl<C> Tracked<C> {
+    /// Stop publishing the gauge. Its last value stays with the recorder.
+    pub fn unpublish_gauge(&mut self) {
+        self.tally.set_gauge(None);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{IndirectHeapSize, Tracked};
+
+/// Number of buckets: one for size zero and one per bit of `usize`.
+const BUCKETS: usize = usize::BITS as usize + 1;
+
+/// Distribution of the heap sizes of the elements of a [`Tracked`]
+/// collection, in power-of-two buckets. For maps, only the values are
+/// counted.
+///
+/// Bucket `0` holds elements of size zero, and bucket `i > 0` holds elements
+/// with a size in `2^(i-1)..2^i`. Percentiles are thus only accurate up to a
+/// factor of two.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeHistogram {
+    counts: [u64; BUCKETS],
+    len: u64,
+}
+
+/// A non-empty bucket of a [`SizeHistogram`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeBucket {
+    /// Smallest size that falls into this bucket.
+    pub min: usize,
+    /// Largest size that falls into this bucket.
+    pub max: usize,
+    /// Number of elements in this bucket.
+    pub count: u64,
+}
+
+impl SizeHistogram {
+    pub(crate) fn new() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+            len: 0,
+        }
+    }
+
+    fn bucket(size: usize) -> usize {
+        (usize::BITS - size.leading_zeros()) as usize
+    }
+
+    fn bounds(bucket: usize) -> (usize, usize) {
+        match bucket {
+            0 => (0, 0),
+            i => (1 << (i - 1), usize::MAX >> (BUCKETS - 1 - i)),
+        }
+    }
+
+    pub(crate) fn insert_many(&mut self, size: usize, n: usize) {
+        self.counts[Self::bucket(size)] += n as u64;
+        self.len += n as u64;
+    }
+
+    pub(crate) fn remove(&mut self, size: usize) {
+        self.counts[Self::bucket(size)] -= 1;
+        self.len -= 1;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn merge(&mut self, other: &Self) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        self.len += other.len;
+    }
+
+    /// Number of elements counted.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The non-empty buckets, from small to large sizes.
+    pub fn buckets(&self) -> impl DoubleEndedIterator<Item = SizeBucket> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, &count)| {
+                let (min, max) = Self::bounds(i);
+                SizeBucket { min, max, count }
+            })
+    }
+
+    /// An upper bound on the size of at least `p` percent of the elements,
+    /// i.e. the largest size of the bucket that the `p`th percentile falls
+    /// into. Returns `None` if there are no elements.
+    ///
+    /// # Panics
+    ///
+    /// If `p` is not within `0.0..=100.0`.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss,
+        reason = "The rank is within 1..=len"
+    )]
+    pub fn percentile(&self, p: f64) -> Option<usize> {
+        assert!(
+            (0.0..=100.0).contains(&p),
+            "percentile must be within 0.0..=100.0, got {p}"
+        );
+        if self.len == 0 {
+            return None;
+        }
+        let rank = ((p / 100.0 * self.len as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets().find_map(|bucket| {
+            seen += bucket.count;
+            (seen >= rank).then_some(bucket.max)
+        })
+    }
+
+    /// Shorthand for the 50th [percentile](Self::percentile).
+    #[must_use]
+    pub fn median(&self) -> Option<usize> {
+        self.percentile(50.0)
+    }
+}
+
+impl FromIterator<usize> for SizeHistogram {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut histogram = Self::new();
+        for size in iter {
+            histogram.insert_many(size, 1);
+        }
+        histogram
+    }
+}
+
+impl<C: IndirectHeapSize> Tracked<C> {
+    /// Start maintaining a histogram of the elements' heap sizes. This walks
+    /// all elements once. Does nothing if the histogram is already enabled.
+    ///
+    /// Afterwards, every insertion, removal and change of an element updates
+    /// one or two counters.
+    pub fn enable_size_histogram(&mut self) {
+        if self.tally.histogram().is_none() {
+            let histogram = self.inner.element_heap_sizes().collect();
+            self.tally.set_histogram(Some(histogram));
+        }
+    }
+}
+
+impl<C> Tracked<C> {
+    pub fn disable_size_histogram(&mut self) {
+        self.tally.set_histogram(None);
+    }
+
+    /// The histogram of the elements' heap sizes, or `None` if it is
+    /// disabled.
+    #[must_use]
+    pub fn size_histogram(&self) -> Option<&SizeHistogram> {
+        self.tally.histogram()
+    }
+}
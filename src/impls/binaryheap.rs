@@ -1,11 +1,19 @@
 use std::{
-    collections::{BinaryHeap, binary_heap::PeekMut},
+    collections::{
+        BinaryHeap,
+        binary_heap::{Drain, PeekMut},
+    },
+    iter::FusedIterator,
     ops::Deref,
 };
 
 use crate::{
     HeapSize, Tracked,
-    macros::{impl_clear, impl_from, impl_new, impl_shallow_heap_size},
+    macros::{
+        impl_capacity, impl_clear, impl_extend, impl_from, impl_new, impl_reserve_exact,
+        impl_shallow_heap_size, impl_shrink,
+    },
+    tally::{ElementSize, Tally},
     tracked_value::TrackedValue,
 };
 
@@ -14,32 +22,118 @@ where
     T: Ord + HeapSize,
 {
     pub fn push(&mut self, item: T) {
-        self.indirect_heap_memory += T::heap_size(&item);
+        self.tally.add(ElementSize::of(&item));
         self.inner.push(item);
+        self.after_op("push");
     }
 
     pub fn pop(&mut self) -> Option<T> {
-        self.inner
+        let value = self
+            .inner
             .pop()
-            .inspect(|v| self.indirect_heap_memory -= T::heap_size(v))
+            .inspect(|v| self.tally.remove(ElementSize::of(v)));
+        self.maybe_shrink();
+        self.after_op("pop");
+        value
     }
 
     pub fn peek_mut(&mut self) -> Option<TrackedPeekMut<'_, T>> {
         let elem = self.inner.peek_mut()?;
         Some(TrackedPeekMut {
-            tracker: &mut self.indirect_heap_memory,
+            tally: &mut self.tally,
             elem,
         })
     }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.inner.retain(|v| {
+            if f(v) {
+                true
+            } else {
+                self.tally.remove(ElementSize::of(v));
+                false
+            }
+        });
+        self.maybe_shrink();
+        self.after_op("retain");
+    }
+
+    /// Clears the heap and returns all removed elements in arbitrary order.
+    /// Like [`BinaryHeap::drain`], the heap is empty afterwards even if the
+    /// iterator is dropped early.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        self.tally.clear();
+        self.after_op("drain");
+        self.inner.drain()
+    }
+
+    /// Clears the heap and returns all removed elements in heap order. If the
+    /// iterator is dropped early, the remaining elements are removed as well.
+    pub fn drain_sorted(&mut self) -> TrackedDrainSorted<'_, T> {
+        TrackedDrainSorted { heap: self }
+    }
+
+    /// Consider using [`Self::append_tracked`].
+    pub fn append(&mut self, other: &mut BinaryHeap<T>) {
+        for elem in &*other {
+            self.tally.add(ElementSize::of(elem));
+        }
+        self.inner.append(other);
+        self.after_op("append");
+    }
+
+    pub fn append_tracked(&mut self, other: &mut Self) {
+        self.absorb_tally(other);
+        self.inner.append(&mut other.inner);
+        self.after_op("append");
+        other.after_op("append");
+    }
+
+    /// Consumes the heap and returns a vector in sorted (ascending) order.
+    /// The tally is moved over without recounting.
+    #[must_use]
+    pub fn into_sorted_tracked_vec(self) -> Tracked<Vec<T>> {
+        self.map_inner(BinaryHeap::into_sorted_vec)
+    }
+}
+
+impl<T: Ord> From<Tracked<Vec<T>>> for Tracked<BinaryHeap<T>> {
+    /// Builds a heap from a tracked vector, moving the tally over without
+    /// recounting.
+    fn from(value: Tracked<Vec<T>>) -> Self {
+        value.map_inner(BinaryHeap::from)
+    }
+}
+
+impl<T> From<Tracked<BinaryHeap<T>>> for Tracked<Vec<T>> {
+    /// Unwraps the heap into a vector in arbitrary order, moving the tally
+    /// over without recounting.
+    fn from(value: Tracked<BinaryHeap<T>>) -> Self {
+        value.map_inner(BinaryHeap::into_vec)
+    }
+}
+
+impl<T: Ord> Tracked<BinaryHeap<T>> {
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_tally(BinaryHeap::with_capacity(capacity), 0)
+    }
 }
 
 impl_new!(BinaryHeap<T>, T: Ord);
-impl_clear!(BinaryHeap<T>);
+impl_clear!(BinaryHeap<T>, capacity);
 impl_from!(BinaryHeap<T>, |v| T::heap_size(v));
+impl_extend!(BinaryHeap<T>, T, T: Ord);
+impl_capacity!(BinaryHeap<T>);
+impl_reserve_exact!(BinaryHeap<T>);
+impl_shrink!(BinaryHeap<T>);
 impl_shallow_heap_size!(BinaryHeap<T>, |v: &Self| v.capacity() * size_of::<T>());
 
 pub struct TrackedPeekMut<'a, T: 'a + Ord> {
-    tracker: &'a mut usize,
+    tally: &'a mut Tally<BinaryHeap<T>>,
     elem: PeekMut<'a, T>,
 }
 
@@ -52,12 +146,40 @@ impl<'a, T: 'a + Ord> Deref for TrackedPeekMut<'a, T> {
 }
 
 impl<'a, T: 'a + Ord + HeapSize> TrackedPeekMut<'a, T> {
-    pub fn get_mut(&'a mut self) -> TrackedValue<'a, T> {
-        TrackedValue::new(self.tracker, &mut *self.elem)
+    pub fn get_mut(&'a mut self) -> TrackedValue<'a, T, &'a mut Tally<BinaryHeap<T>>> {
+        TrackedValue::new(self.tally, &mut *self.elem)
     }
 
     pub fn pop(self) -> T {
-        *self.tracker -= T::heap_size(&self.elem);
+        self.tally.remove(ElementSize::of(&*self.elem));
+        self.tally.after_guard_op("pop");
         PeekMut::pop(self.elem)
     }
 }
+
+pub struct TrackedDrainSorted<'a, T: Ord + HeapSize> {
+    heap: &'a mut Tracked<BinaryHeap<T>>,
+}
+
+impl<T: Ord + HeapSize> Iterator for TrackedDrainSorted<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.heap.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.heap.inner.len();
+        (len, Some(len))
+    }
+}
+
+impl<T: Ord + HeapSize> ExactSizeIterator for TrackedDrainSorted<'_, T> {}
+
+impl<T: Ord + HeapSize> FusedIterator for TrackedDrainSorted<'_, T> {}
+
+impl<T: Ord + HeapSize> Drop for TrackedDrainSorted<'_, T> {
+    fn drop(&mut self) {
+        self.heap.clear();
+    }
+}
@@ -1,46 +1,221 @@
-use std::ops::Deref;
+use std::{cmp::Ordering, ops::Deref};
 
-use binary_heap_plus::{BinaryHeap, PeekMut};
+use binary_heap_plus::{
+    BinaryHeap, Drain, FnComparator, KeyComparator, MaxComparator, MinComparator, PeekMut,
+};
 use compare::Compare;
 
 use crate::{
     HeapSize, Tracked,
-    macros::{impl_clear, impl_from, impl_new, impl_shallow_heap_size},
+    macros::{impl_clear, impl_extend, impl_from, impl_shallow_heap_size, impl_shrink},
+    tally::{ElementSize, Tally},
     tracked_value::TrackedValue,
 };
 
+impl<T, C> Tracked<BinaryHeap<T, C>>
+where
+    C: Compare<T> + Default,
+{
+    /// Creates an empty heap ordered by the default value of `C`, e.g. a
+    /// max-heap for [`MaxComparator`] and a min-heap for [`MinComparator`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_tally(BinaryHeap::from_vec(Vec::new()), 0)
+    }
+}
+
+impl<T, C> Tracked<BinaryHeap<T, C>>
+where
+    C: Compare<T>,
+{
+    /// Creates an empty heap ordered by `cmp`.
+    #[must_use]
+    pub fn new_by_cmp(cmp: C) -> Self {
+        Self::with_capacity_by_cmp(0, cmp)
+    }
+
+    /// Creates an empty heap ordered by `cmp` with space for at least
+    /// `capacity` elements.
+    #[must_use]
+    pub fn with_capacity_by_cmp(capacity: usize, cmp: C) -> Self {
+        Self::with_tally(
+            BinaryHeap::from_vec_cmp(Vec::with_capacity(capacity), cmp),
+            0,
+        )
+    }
+}
+
+impl<T: Ord> Tracked<BinaryHeap<T, MaxComparator>> {
+    #[must_use]
+    pub fn new_max() -> Self {
+        Self::new_by_cmp(MaxComparator)
+    }
+
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_by_cmp(capacity, MaxComparator)
+    }
+}
+
+impl<T: Ord> Tracked<BinaryHeap<T, MinComparator>> {
+    #[must_use]
+    pub fn new_min() -> Self {
+        Self::new_by_cmp(MinComparator)
+    }
+
+    #[must_use]
+    pub fn with_capacity_min(capacity: usize) -> Self {
+        Self::with_capacity_by_cmp(capacity, MinComparator)
+    }
+}
+
+impl<T, F> Tracked<BinaryHeap<T, FnComparator<F>>>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    #[must_use]
+    pub fn new_by(f: F) -> Self {
+        Self::new_by_cmp(FnComparator(f))
+    }
+
+    #[must_use]
+    pub fn with_capacity_by(capacity: usize, f: F) -> Self {
+        Self::with_capacity_by_cmp(capacity, FnComparator(f))
+    }
+}
+
+impl<T, F, K> Tracked<BinaryHeap<T, KeyComparator<F>>>
+where
+    F: Fn(&T) -> K,
+    K: Ord,
+{
+    #[must_use]
+    pub fn new_by_key(f: F) -> Self {
+        Self::new_by_cmp(KeyComparator(f))
+    }
+
+    #[must_use]
+    pub fn with_capacity_by_key(capacity: usize, f: F) -> Self {
+        Self::with_capacity_by_cmp(capacity, KeyComparator(f))
+    }
+}
+
 impl<T, C> Tracked<BinaryHeap<T, C>>
 where
     T: HeapSize,
     C: Compare<T>,
 {
     pub fn push(&mut self, item: T) {
-        self.indirect_heap_memory += T::heap_size(&item);
+        self.tally.add(ElementSize::of(&item));
         self.inner.push(item);
+        self.after_op("push");
     }
 
     pub fn pop(&mut self) -> Option<T> {
-        self.inner
+        let value = self
+            .inner
             .pop()
-            .inspect(|v| self.indirect_heap_memory -= T::heap_size(v))
+            .inspect(|v| self.tally.remove(ElementSize::of(v)));
+        self.maybe_shrink();
+        self.after_op("pop");
+        value
     }
 
     pub fn peek_mut(&mut self) -> Option<TrackedPeekMut<'_, T, C>> {
         let elem = self.inner.peek_mut()?;
         Some(TrackedPeekMut {
-            tracker: &mut self.indirect_heap_memory,
+            tally: &mut self.tally,
             elem,
         })
     }
+
+    /// Replaces the comparator and rebuilds the heap. The elements and thus
+    /// the tally stay the same.
+    pub fn replace_cmp(&mut self, cmp: C) {
+        self.inner.replace_cmp(cmp);
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        // `binary_heap_plus` has no `retain`, so rebuild from the survivors.
+        let kept: Vec<T> = self
+            .inner
+            .drain()
+            .filter(|v| {
+                if f(v) {
+                    true
+                } else {
+                    self.tally.remove(ElementSize::of(v));
+                    false
+                }
+            })
+            .collect();
+        self.inner.extend(kept);
+        self.maybe_shrink();
+        self.after_op("retain");
+    }
+
+    /// Clears the heap and returns all removed elements in arbitrary order.
+    /// The heap is empty afterwards even if the iterator is dropped early.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        self.tally.clear();
+        self.after_op("drain");
+        self.inner.drain()
+    }
+
+    /// Consumes the heap and returns its elements in arbitrary order. The
+    /// tally is moved over without recounting.
+    #[must_use]
+    pub fn into_tracked_vec(self) -> Tracked<Vec<T>> {
+        self.map_inner(BinaryHeap::into_vec)
+    }
+
+    /// Consumes the heap and returns its elements sorted in ascending order
+    /// according to the comparator. The tally is moved over without
+    /// recounting.
+    #[must_use]
+    pub fn into_sorted_tracked_vec(self) -> Tracked<Vec<T>> {
+        self.map_inner(BinaryHeap::into_sorted_vec)
+    }
+}
+
+impl<T, C> Tracked<BinaryHeap<T, C>> {
+    /// Reserves capacity for at least `additional` more elements.
+    pub fn reserve(&mut self, additional: usize) {
+        self.inner.reserve(additional);
+        self.after_op("reserve");
+    }
+
+    /// Reserves the minimum capacity for at least `additional` more
+    /// elements.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.inner.reserve_exact(additional);
+        self.after_op("reserve_exact");
+    }
+
+    /// Shrinks the capacity as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit();
+        self.after_op("shrink_to_fit");
+    }
+
+    /// Shrinks the capacity with a lower bound of `min_capacity`.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.inner.shrink_to(min_capacity);
+        self.after_op("shrink_to");
+    }
 }
 
-impl_new!(BinaryHeap<T>, T: Ord);
-impl_clear!(BinaryHeap<T, C>);
+impl_clear!(BinaryHeap<T, C>, capacity);
 impl_from!(BinaryHeap<T, C>, |v| T::heap_size(v), T);
+impl_extend!(BinaryHeap<T, C>, T, C: Compare<T>);
+impl_shrink!(BinaryHeap<T, C>);
 impl_shallow_heap_size!(BinaryHeap<T, C>, |v: &Self| v.capacity() * size_of::<T>());
 
 pub struct TrackedPeekMut<'a, T: 'a, C: 'a + Compare<T>> {
-    tracker: &'a mut usize,
+    tally: &'a mut Tally<BinaryHeap<T, C>>,
     elem: PeekMut<'a, T, C>,
 }
 
@@ -53,12 +228,13 @@ impl<'a, T: 'a, C: 'a + Compare<T>> Deref for TrackedPeekMut<'a, T, C> {
 }
 
 impl<'a, T: 'a + HeapSize, C: 'a + Compare<T>> TrackedPeekMut<'a, T, C> {
-    pub fn get_mut(&'a mut self) -> TrackedValue<'a, T> {
-        TrackedValue::new(self.tracker, &mut *self.elem)
+    pub fn get_mut(&'a mut self) -> TrackedValue<'a, T, &'a mut Tally<BinaryHeap<T, C>>> {
+        TrackedValue::new(self.tally, &mut *self.elem)
     }
 
     pub fn pop(self) -> T {
-        *self.tracker -= T::heap_size(&self.elem);
+        self.tally.remove(ElementSize::of(&*self.elem));
+        self.tally.after_guard_op("pop");
         PeekMut::pop(self.elem)
     }
 }
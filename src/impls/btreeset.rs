@@ -1,8 +1,16 @@
-use std::{borrow::Borrow, collections::BTreeSet};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeSet, btree_set::IntoIter},
+    iter::FusedIterator,
+    mem,
+    ops::RangeBounds,
+    vec,
+};
 
 use crate::{
     HeapSize, Tracked,
     macros::{impl_clear, impl_from, impl_new, impl_shallow_heap_size},
+    tally::ElementSize,
 };
 
 impl<T> Tracked<BTreeSet<T>>
@@ -10,13 +18,13 @@ where
     T: Ord + HeapSize,
 {
     pub fn insert(&mut self, key: T) -> bool {
-        let key_size = T::heap_size(&key);
-        if self.inner.insert(key) {
-            self.indirect_heap_memory += key_size;
-            true
-        } else {
-            false
+        let key_size = ElementSize::of(&key);
+        let inserted = self.inner.insert(key);
+        if inserted {
+            self.tally.add(key_size);
         }
+        self.after_op("insert");
+        inserted
     }
 
     pub fn remove<Q>(&mut self, key: &Q) -> bool
@@ -24,12 +32,50 @@ where
         T: Borrow<Q>,
         Q: Ord + ?Sized,
     {
-        if let Some(k) = self.inner.take(key) {
-            self.indirect_heap_memory -= T::heap_size(&k);
-            true
-        } else {
-            false
-        }
+        self.take(key).is_some()
+    }
+
+    pub fn take<Q>(&mut self, key: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let value = self
+            .inner
+            .take(key)
+            .inspect(|k| self.tally.remove(ElementSize::of(k)));
+        self.after_op("take");
+        value
+    }
+
+    /// Adds a value to the set, replacing the existing equal value, if any.
+    /// Returns the replaced value.
+    pub fn replace(&mut self, key: T) -> Option<T> {
+        self.tally.add(ElementSize::of(&key));
+        let old = self
+            .inner
+            .replace(key)
+            .inspect(|old| self.tally.remove(ElementSize::of(old)));
+        self.after_op("replace");
+        old
+    }
+
+    pub fn pop_first(&mut self) -> Option<T> {
+        let value = self
+            .inner
+            .pop_first()
+            .inspect(|k| self.tally.remove(ElementSize::of(k)));
+        self.after_op("pop_first");
+        value
+    }
+
+    pub fn pop_last(&mut self) -> Option<T> {
+        let value = self
+            .inner
+            .pop_last()
+            .inspect(|k| self.tally.remove(ElementSize::of(k)));
+        self.after_op("pop_last");
+        value
     }
 
     pub fn retain<F>(&mut self, mut f: F)
@@ -40,10 +86,153 @@ where
             if f(key) {
                 true
             } else {
-                self.indirect_heap_memory -= T::heap_size(key);
+                self.tally.remove(ElementSize::of(key));
                 false
             }
         });
+        self.after_op("retain");
+    }
+
+    /// Clears the set and returns all removed elements in ascending order.
+    pub fn drain(&mut self) -> IntoIter<T> {
+        let inner = mem::take(&mut self.inner);
+        self.tally.clear();
+        self.after_op("drain");
+        inner.into_iter()
+    }
+
+    /// Removes and yields all elements within `range` for which `pred`
+    /// returns `true`. Elements that are not yielded stay in the set and
+    /// remain counted.
+    ///
+    /// Unlike [`BTreeSet::extract_if`], `pred` is called on all elements
+    /// within `range` up front, as the iterator needs the set itself to put
+    /// back what it did not yield.
+    pub fn extract_if<R, F>(&mut self, range: R, pred: F) -> TrackedExtractIf<'_, T>
+    where
+        R: RangeBounds<T>,
+        F: FnMut(&T) -> bool,
+    {
+        let extracted: Vec<T> = self.inner.extract_if(range, pred).collect();
+        TrackedExtractIf {
+            extracted: extracted.into_iter(),
+            set: self,
+        }
+    }
+
+    /// Returns a reference to the value equal to `key`, inserting the value
+    /// computed by `f` if there is none.
+    ///
+    /// # Panics
+    ///
+    /// If the value computed by `f` is not equal to `key`.
+    pub fn get_or_insert_with<Q, F>(&mut self, key: &Q, f: F) -> &T
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+        F: FnOnce(&Q) -> T,
+    {
+        if !self.inner.contains(key) {
+            let value = f(key);
+            assert!(value.borrow() == key, "new value is not equal to the key");
+            self.tally.add(ElementSize::of(&value));
+            self.inner.insert(value);
+            self.after_op("get_or_insert_with");
+        }
+        self.inner
+            .get(key)
+            .expect("value is present after insertion")
+    }
+
+    /// Splits the set in two at `key`. Returns everything after and including
+    /// `key`, with its share of the tally moved along.
+    #[must_use]
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut split = Self::with_tally(self.inner.split_off(key), 0);
+        for size in split.inner.iter().map(|v| ElementSize::of(v)) {
+            self.tally.remove(size);
+            split.tally.add(size);
+        }
+        self.after_op("split_off");
+        split
+    }
+
+    /// Moves all elements from `other` into `self`. Elements already present
+    /// in `self` are kept and the duplicates from `other` are dropped.
+    ///
+    /// To move the elements of another tracked set, use
+    /// [`Self::append_tracked`].
+    pub fn append(&mut self, other: &mut BTreeSet<T>) {
+        for elem in mem::take(other) {
+            let size = ElementSize::of(&elem);
+            if self.inner.insert(elem) {
+                self.tally.add(size);
+            }
+        }
+        self.after_op("append");
+    }
+
+    /// Like [`Self::append`], but also clears the tally of `other`.
+    pub fn append_tracked(&mut self, other: &mut Self) {
+        self.append(&mut other.inner);
+        other.tally.clear();
+        other.after_op("append");
+    }
+}
+
+impl<T> Tracked<BTreeSet<T>>
+where
+    T: Ord + HeapSize + Clone,
+{
+    /// Builds a tracked set of all values in `self` or `other`.
+    #[must_use]
+    pub fn union_tracked(&self, other: &BTreeSet<T>) -> Self {
+        self.inner.union(other).cloned().collect()
+    }
+
+    /// Builds a tracked set of all values in both `self` and `other`.
+    #[must_use]
+    pub fn intersection_tracked(&self, other: &BTreeSet<T>) -> Self {
+        self.inner.intersection(other).cloned().collect()
+    }
+
+    /// Builds a tracked set of all values in `self` but not in `other`.
+    #[must_use]
+    pub fn difference_tracked(&self, other: &BTreeSet<T>) -> Self {
+        self.inner.difference(other).cloned().collect()
+    }
+
+    /// Builds a tracked set of all values in exactly one of `self` and
+    /// `other`.
+    #[must_use]
+    pub fn symmetric_difference_tracked(&self, other: &BTreeSet<T>) -> Self {
+        self.inner.symmetric_difference(other).cloned().collect()
+    }
+}
+
+/// Values equal to one already in the set are dropped, like
+/// [`Tracked::insert`] does.
+impl<T: Ord + HeapSize> Extend<T> for Tracked<BTreeSet<T>> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for key in iter {
+            let key_size = ElementSize::of(&key);
+            if self.inner.insert(key) {
+                self.tally.add(key_size);
+            }
+        }
+        self.after_op("extend");
+    }
+}
+
+impl<T: Ord + HeapSize> FromIterator<T> for Tracked<BTreeSet<T>> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
     }
 }
 
@@ -52,3 +241,43 @@ impl_clear!(BTreeSet<T>);
 impl_from!(BTreeSet<T>, |v| T::heap_size(v));
 impl_shallow_heap_size!(BTreeSet<T>, |v: &Self| v.len()
     * (size_of::<T>() + size_of::<usize>()));
+
+/// Iterator returned by [`Tracked::extract_if`] on a `BTreeSet`.
+pub struct TrackedExtractIf<'a, T>
+where
+    T: Ord + HeapSize,
+{
+    // Taken out of the set, but still counted until yielded.
+    extracted: vec::IntoIter<T>,
+    set: &'a mut Tracked<BTreeSet<T>>,
+}
+
+impl<T> Iterator for TrackedExtractIf<'_, T>
+where
+    T: Ord + HeapSize,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.extracted.next()?;
+        self.set.tally.remove(ElementSize::of(&key));
+        Some(key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.extracted.size_hint()
+    }
+}
+
+impl<T> FusedIterator for TrackedExtractIf<'_, T> where T: Ord + HeapSize {}
+
+impl<T> Drop for TrackedExtractIf<'_, T>
+where
+    T: Ord + HeapSize,
+{
+    fn drop(&mut self) {
+        // Like `BTreeSet::extract_if`, keep what was not yielded.
+        self.set.inner.extend(self.extracted.by_ref());
+        self.set.after_op("extract_if");
+    }
+}
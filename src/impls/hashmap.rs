@@ -1,13 +1,15 @@
 use std::{
     borrow::Borrow,
-    collections::{HashMap, hash_map::Entry},
-    hash::{BuildHasher, Hash},
+    cell::Cell,
+    collections::{HashMap, HashSet, hash_map::Entry},
+    hash::{BuildHasher, Hash, RandomState},
 };
 
 use crate::{
-    HeapSize, Tracked,
-    macros::{impl_clear, impl_from, impl_new, impl_shallow_heap_size},
-    tracked_value::TrackedValue,
+    HeapSize, IndirectHeapSize, ShallowHeapSize, Tracked,
+    macros::{impl_capacity, impl_clear, impl_from, impl_new, impl_shallow_heap_size, impl_shrink},
+    tally::{ElementSize, Tally},
+    tracked_value::{IndexKey, Keyed, SharedMapValue, TrackedBatch, TrackedMapValue, TrackedValue},
 };
 
 impl<K, V, S> Tracked<HashMap<K, V, S>>
@@ -17,17 +19,25 @@ where
     S: BuildHasher,
 {
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        self.indirect_heap_memory += V::heap_size(&value);
+        self.sync_largest();
+        let value_size = ElementSize::of(&value);
+        let old = self.insert_sized(key, value, value_size);
+        self.after_op("insert");
+        old
+    }
+
+    /// Inserts `value` of heap size `value_size`. Neither syncs the index
+    /// of the largest entries nor calls `after_op`.
+    pub(crate) fn insert_sized(&mut self, key: K, value: V, value_size: ElementSize) -> Option<V> {
         match self.inner.entry(key) {
             Entry::Occupied(mut o) => {
-                // Subtract old value
-                self.indirect_heap_memory -= V::heap_size(o.get());
-                let old = o.insert(value);
-                Some(old)
+                self.tally
+                    .resize_value(Some(o.key()), ElementSize::of(o.get()), value_size);
+                Some(o.insert(value))
             }
             Entry::Vacant(v) => {
-                // Add key
-                self.indirect_heap_memory += K::heap_size(v.key());
+                let key_size = K::heap_size(v.key());
+                self.tally.add_entry(v.key(), key_size, value_size);
                 v.insert(value);
                 None
             }
@@ -39,13 +49,7 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.inner
-            .remove_entry(key)
-            .inspect(|(k, v)| {
-                self.indirect_heap_memory -= K::heap_size(k);
-                self.indirect_heap_memory -= V::heap_size(v);
-            })
-            .map(|(_, v)| v)
+        self.remove_entry(key).map(|(_, v)| v)
     }
 
     pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
@@ -53,77 +57,365 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.inner.remove_entry(key).inspect(|(k, v)| {
-            self.indirect_heap_memory -= K::heap_size(k);
-            self.indirect_heap_memory -= V::heap_size(v);
-        })
+        self.sync_largest();
+        let entry = self.inner.remove_entry(key).inspect(|(k, v)| {
+            self.tally
+                .remove_entry(k, K::heap_size(k), ElementSize::of(v));
+        });
+        self.maybe_shrink();
+        self.after_op("remove");
+        entry
     }
 
-    pub fn entry(&mut self, key: K) -> TrackedEntry<'_, K, V> {
+    pub fn entry(&mut self, key: K) -> TrackedEntry<'_, K, V, S> {
+        self.sync_largest();
+        // `HashMap::entry` makes room for a vacant key. Do that up front if
+        // the shallow heap size is observed, so the entry knows the final
+        // one. Keys that are present never grow the map.
+        if self.tally.observes_shallow() && !self.inner.contains_key(&key) {
+            self.inner.reserve(1);
+        }
+        let shallow = self.shallow_heap_size();
         match self.inner.entry(key) {
-            std::collections::hash_map::Entry::Occupied(o) => {
-                TrackedEntry::Occupied(TrackedOccupiedEntry {
-                    tracker: &mut self.indirect_heap_memory,
-                    entry: o,
-                })
-            }
-            std::collections::hash_map::Entry::Vacant(v) => {
-                TrackedEntry::Vacant(TrackedVacantEntry {
-                    tracker: &mut self.indirect_heap_memory,
-                    entry: v,
-                })
-            }
+            Entry::Occupied(o) => TrackedEntry::Occupied(TrackedOccupiedEntry {
+                tally: &mut self.tally,
+                entry: o,
+                shallow,
+            }),
+            Entry::Vacant(v) => TrackedEntry::Vacant(TrackedVacantEntry {
+                tally: &mut self.tally,
+                entry: v,
+                shallow,
+            }),
         }
     }
 
-    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<TrackedValue<'_, V>>
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<TrackedMapValue<'_, HashMap<K, V, S>, V>>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
+        self.sync_largest();
+        // Only look up the key if the index of the largest entries needs it.
+        let index_key = self
+            .tally
+            .largest()
+            .and_then(|_| self.inner.get_key_value(key))
+            .and_then(|(k, _)| self.tally.index_key_owned(k));
         self.inner
             .get_mut(key)
-            .map(|v| TrackedValue::new(&mut self.indirect_heap_memory, v))
+            .map(|v| TrackedValue::new(Keyed::new(&mut self.tally, index_key), v))
+    }
+
+    /// Iterates over the entries with mutable access to the values. Each
+    /// value is measured again when its guard is dropped.
+    pub fn iter_mut_tracked(
+        &mut self,
+    ) -> impl ExactSizeIterator<Item = (&K, SharedMapValue<'_, HashMap<K, V, S>, V>)> {
+        self.sync_largest();
+        let indexed = self.tally.largest().is_some();
+        let tally = Cell::from_mut(&mut self.tally);
+        self.inner.iter_mut().map(move |(k, v)| {
+            let index_key = indexed.then_some(IndexKey::Borrowed(k));
+            (k, TrackedValue::new(Keyed::new(tally, index_key), v))
+        })
+    }
+
+    /// Applies `f` to every entry. Unlike [`Self::iter_mut_tracked`], no
+    /// guard is created per value, which is cheaper for bulk updates.
+    pub fn for_each_mut_batched<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V),
+    {
+        self.sync_largest();
+        let mut batch = TrackedBatch::new(&mut self.tally);
+        for (k, v) in &mut self.inner {
+            batch.update_value(k, v, |v| f(k, v));
+        }
+        self.after_op("for_each_mut_batched");
+    }
+}
+
+impl<K, V, S> Tracked<HashMap<K, V, S>>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: HeapSize,
+{
+    /// Start maintaining an index of the keys by the heap size of their
+    /// values, so that [`Self::largest`] does not have to scan the map. This
+    /// walks all entries once. Does nothing if the index is already enabled.
+    ///
+    /// Afterwards, every insertion, removal and change of a value also
+    /// updates the index, which stores a clone of every key.
+    pub fn enable_largest_index(&mut self) {
+        self.enable_largest_with::<K, V, HashSet<K>>();
+    }
+
+    pub fn disable_largest_index(&mut self) {
+        self.tally.set_largest(None);
+    }
+
+    /// The keys of the `n` entries whose values allocate the most heap
+    /// memory, largest first. With the index enabled, this takes
+    /// O(n + log len), otherwise it scans all entries.
+    ///
+    /// After [`Tracked::with_inner_mut`], `recalculate` or `par_recalculate`,
+    /// the index is rebuilt by the next mutating operation, and until then,
+    /// this scans as well.
+    #[must_use]
+    pub fn largest(&self, n: usize) -> Vec<&K> {
+        self.largest_with(n)
+    }
+}
+
+impl<K, V> Tracked<HashMap<K, V, RandomState>> {
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_tally(HashMap::with_capacity(capacity), 0)
+    }
+}
+
+impl<K, V, S> Tracked<HashMap<K, V, S>> {
+    #[must_use]
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_tally(HashMap::with_hasher(hash_builder), 0)
+    }
+
+    #[must_use]
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self::with_tally(HashMap::with_capacity_and_hasher(capacity, hash_builder), 0)
     }
 }
 
 impl_new!(HashMap<K, V, S>, S: BuildHasher + Default);
-impl_clear!(HashMap<K, V, S>);
-impl_from!(HashMap<K, V, S>, |(k, v)| K::heap_size(k) + V::heap_size(v), K, V);
+impl<K, V, S> Tracked<HashMap<K, V, S>> {
+    /// Heap memory allocated by the keys.
+    #[must_use]
+    pub fn key_heap_size(&self) -> usize {
+        self.tally.keys()
+    }
+
+    /// Heap memory allocated by the values.
+    #[must_use]
+    pub fn value_heap_size(&self) -> usize {
+        // Removals from an estimated tally stop at zero, so the keys may
+        // exceed the total.
+        self.tally.indirect().saturating_sub(self.tally.keys())
+    }
+
+    /// Like [`Tracked::into_parts`], but keeps the split between keys and
+    /// values. Returns the collection, the heap memory allocated by all
+    /// elements, and the part of it allocated by the keys.
+    pub fn into_parts_with_keys(self) -> (HashMap<K, V, S>, usize, usize) {
+        (self.inner, self.tally.indirect(), self.tally.keys())
+    }
+}
+
+impl<K, V, S> Tracked<HashMap<K, V, S>>
+where
+    K: HeapSize,
+    V: HeapSize,
+{
+    /// Like [`Tracked::from_parts_unchecked`], but also restores the split
+    /// between keys and values. In debug builds, both claims are verified by
+    /// a recount.
+    ///
+    /// # Safety
+    ///
+    /// `indirect_heap_memory` and `key_heap_memory` must be exactly the heap
+    /// sizes as [`Self::into_parts_with_keys`] returns them. A wrong value
+    /// does not cause undefined behavior within this crate, but all reported
+    /// sizes will be off, and removals may panic on underflow.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, if either value is wrong.
+    pub unsafe fn from_parts_with_keys_unchecked(
+        inner: HashMap<K, V, S>,
+        indirect_heap_memory: usize,
+        key_heap_memory: usize,
+    ) -> Self {
+        debug_assert_eq!(
+            inner.indirect_heap_size(),
+            indirect_heap_memory,
+            "from_parts_with_keys_unchecked was given a wrong heap size"
+        );
+        debug_assert_eq!(
+            inner.indirect_key_heap_size(),
+            key_heap_memory,
+            "from_parts_with_keys_unchecked was given a wrong key heap size"
+        );
+        let mut tally = Tally::new(indirect_heap_memory, key_heap_memory);
+        tally.restore_shared(inner.shared_allocations());
+        Self {
+            tally,
+            ..Self::with_tally(inner, 0)
+        }
+    }
+}
+
+/// Like [`Tracked::insert`], an existing value is replaced, but its key is
+/// kept.
+impl<K, V, S> Extend<(K, V)> for Tracked<HashMap<K, V, S>>
+where
+    K: Eq + Hash + HeapSize,
+    V: HeapSize,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.sync_largest();
+        let iter = iter.into_iter();
+        // Reserve like `HashMap::extend`, which assumes that duplicates are
+        // common once the map is not empty.
+        let additional = if self.inner.is_empty() {
+            iter.size_hint().0
+        } else {
+            iter.size_hint().0.div_ceil(2)
+        };
+        self.inner.reserve(additional);
+        for (key, value) in iter {
+            let value_size = ElementSize::of(&value);
+            self.insert_sized(key, value, value_size);
+        }
+        self.after_op("extend");
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for Tracked<HashMap<K, V, S>>
+where
+    K: Eq + Hash + HeapSize,
+    V: HeapSize,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::with_hasher(S::default());
+        map.extend(iter);
+        map
+    }
+}
+
+impl_clear!(HashMap<K, V, S>, capacity);
+impl_from!(HashMap<K, V, S>, |v| V::heap_size(v), keys: |k| K::heap_size(k), K, V);
+impl_capacity!(HashMap<K, V, S>, K: Eq + Hash, S: BuildHasher);
+impl_shrink!(HashMap<K, V, S>, K: Eq + Hash, S: BuildHasher);
 impl_shallow_heap_size!(HashMap<K, V, S>, |v: &Self| v.capacity() * (size_of::<K>() + size_of::<V>() + size_of::<usize>()));
 
-pub enum TrackedEntry<'a, K, V> {
-    Occupied(TrackedOccupiedEntry<'a, K, V>),
-    Vacant(TrackedVacantEntry<'a, K, V>),
+pub enum TrackedEntry<'a, K, V, S> {
+    Occupied(TrackedOccupiedEntry<'a, K, V, S>),
+    Vacant(TrackedVacantEntry<'a, K, V, S>),
 }
 
-pub struct TrackedOccupiedEntry<'a, K, V> {
-    tracker: &'a mut usize,
+impl<'a, K, V, S> TrackedEntry<'a, K, V, S>
+where
+    K: Eq + Hash + HeapSize,
+    V: HeapSize,
+{
+    #[must_use]
+    pub fn key(&self) -> &K {
+        match self {
+            TrackedEntry::Occupied(o) => o.key(),
+            TrackedEntry::Vacant(v) => v.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> TrackedMapValue<'a, HashMap<K, V, S>, V> {
+        match self {
+            TrackedEntry::Occupied(o) => o.into_mut(),
+            TrackedEntry::Vacant(v) => v.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F>(self, default: F) -> TrackedMapValue<'a, HashMap<K, V, S>, V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            TrackedEntry::Occupied(o) => o.into_mut(),
+            TrackedEntry::Vacant(v) => v.insert(default()),
+        }
+    }
+
+    pub fn or_insert_with_key<F>(self, default: F) -> TrackedMapValue<'a, HashMap<K, V, S>, V>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        match self {
+            TrackedEntry::Occupied(o) => o.into_mut(),
+            TrackedEntry::Vacant(v) => {
+                let value = default(v.key());
+                v.insert(value)
+            }
+        }
+    }
+
+    /// Provides in-place mutable access to an occupied entry before any
+    /// potential inserts into the map. The change in size is accounted for.
+    #[must_use]
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        if let TrackedEntry::Occupied(o) = &mut self {
+            f(&mut o.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V, S> TrackedEntry<'a, K, V, S>
+where
+    K: Eq + Hash + HeapSize,
+    V: HeapSize + Default,
+{
+    pub fn or_default(self) -> TrackedMapValue<'a, HashMap<K, V, S>, V> {
+        self.or_insert_with(V::default)
+    }
+}
+
+pub struct TrackedOccupiedEntry<'a, K, V, S> {
+    tally: &'a mut Tally<HashMap<K, V, S>>,
     entry: std::collections::hash_map::OccupiedEntry<'a, K, V>,
+    /// Shallow heap size of the map, which entries cannot change.
+    shallow: usize,
 }
 
-impl<'a, K, V> TrackedOccupiedEntry<'a, K, V>
+impl<'a, K, V, S> TrackedOccupiedEntry<'a, K, V, S>
 where
     K: Eq + Hash + HeapSize,
     V: HeapSize,
 {
+    #[must_use]
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
     #[must_use]
     pub fn get(&self) -> &V {
         self.entry.get()
     }
 
+    pub fn get_mut(&mut self) -> TrackedMapValue<'_, HashMap<K, V, S>, V> {
+        let index_key = self.tally.index_key_owned(self.entry.key());
+        TrackedValue::new(
+            Keyed::new(&mut *self.tally, index_key),
+            self.entry.get_mut(),
+        )
+    }
+
     #[must_use]
-    pub fn into_mut(self) -> TrackedValue<'a, V> {
-        TrackedValue::new(self.tracker, self.entry.into_mut())
+    pub fn into_mut(self) -> TrackedMapValue<'a, HashMap<K, V, S>, V> {
+        let index_key = self.tally.index_key_owned(self.entry.key());
+        TrackedValue::new(Keyed::new(self.tally, index_key), self.entry.into_mut())
     }
 
     pub fn insert(&mut self, value: V) -> V {
         let old_value = self.entry.insert(value);
-        let old_size = V::heap_size(&old_value);
-        let new_size = V::heap_size(self.entry.get());
+        let old_size = ElementSize::of(&old_value);
+        let new_size = ElementSize::of(self.entry.get());
 
-        *self.tracker -= old_size;
-        *self.tracker += new_size;
+        self.tally
+            .resize_value(Some(self.entry.key()), old_size, new_size);
+        self.tally.after_op("insert", || self.shallow);
 
         old_value
     }
@@ -134,26 +426,43 @@ where
     )]
     pub fn remove(self) -> V {
         let key_size = K::heap_size(self.entry.key());
-        let val_size = V::heap_size(self.entry.get());
-        *self.tracker -= key_size + val_size;
+        let val_size = ElementSize::of(self.entry.get());
+        self.tally
+            .remove_entry(self.entry.key(), key_size, val_size);
+        self.tally.after_op("remove", || self.shallow);
         self.entry.remove()
     }
 }
 
-pub struct TrackedVacantEntry<'a, K, V> {
-    tracker: &'a mut usize,
+pub struct TrackedVacantEntry<'a, K, V, S> {
+    tally: &'a mut Tally<HashMap<K, V, S>>,
     entry: std::collections::hash_map::VacantEntry<'a, K, V>,
+    /// Shallow heap size of the map, which entries cannot change.
+    shallow: usize,
 }
 
-impl<'a, K, V> TrackedVacantEntry<'a, K, V>
+impl<'a, K, V, S> TrackedVacantEntry<'a, K, V, S>
 where
     K: HeapSize,
     V: HeapSize,
 {
-    pub fn insert(self, value: V) -> &'a mut V {
+    #[must_use]
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    /// Inserts `value` and returns a guard that keeps later changes to it
+    /// tracked.
+    pub fn insert(self, value: V) -> TrackedMapValue<'a, HashMap<K, V, S>, V> {
         let k_size = K::heap_size(self.entry.key());
-        let v_size = V::heap_size(&value);
-        *self.tracker += k_size + v_size;
-        self.entry.insert(value)
+        let v_size = ElementSize::of(&value);
+        self.tally.add_entry(self.entry.key(), k_size, v_size);
+        self.tally.after_op("insert", || self.shallow);
+        let index_key = self.tally.index_key_owned(self.entry.key());
+        TrackedValue::with_size(
+            Keyed::new(self.tally, index_key),
+            self.entry.insert(value),
+            v_size,
+        )
     }
 }
@@ -1,12 +1,15 @@
 use std::{
     borrow::Borrow,
-    collections::HashSet,
-    hash::{BuildHasher, Hash},
+    collections::{HashSet, hash_set::Drain},
+    hash::{BuildHasher, Hash, RandomState},
+    iter::FusedIterator,
+    vec,
 };
 
 use crate::{
     HeapSize, Tracked,
-    macros::{impl_clear, impl_from, impl_new, impl_shallow_heap_size},
+    macros::{impl_capacity, impl_clear, impl_from, impl_new, impl_shallow_heap_size, impl_shrink},
+    tally::ElementSize,
 };
 
 impl<T, S> Tracked<HashSet<T, S>>
@@ -15,13 +18,13 @@ where
     S: BuildHasher,
 {
     pub fn insert(&mut self, key: T) -> bool {
-        let key_size = T::heap_size(&key);
-        if self.inner.insert(key) {
-            self.indirect_heap_memory += key_size;
-            true
-        } else {
-            false
+        let key_size = ElementSize::of(&key);
+        let inserted = self.inner.insert(key);
+        if inserted {
+            self.tally.add(key_size);
         }
+        self.after_op("insert");
+        inserted
     }
 
     pub fn remove<Q>(&mut self, key: &Q) -> bool
@@ -29,12 +32,33 @@ where
         T: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        if let Some(k) = self.inner.take(key) {
-            self.indirect_heap_memory -= T::heap_size(&k);
-            true
-        } else {
-            false
-        }
+        self.take(key).is_some()
+    }
+
+    pub fn take<Q>(&mut self, key: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let value = self
+            .inner
+            .take(key)
+            .inspect(|k| self.tally.remove(ElementSize::of(k)));
+        self.maybe_shrink();
+        self.after_op("take");
+        value
+    }
+
+    /// Adds a value to the set, replacing the existing equal value, if any.
+    /// Returns the replaced value.
+    pub fn replace(&mut self, key: T) -> Option<T> {
+        self.tally.add(ElementSize::of(&key));
+        let old = self
+            .inner
+            .replace(key)
+            .inspect(|old| self.tally.remove(ElementSize::of(old)));
+        self.after_op("replace");
+        old
     }
 
     pub fn retain<F>(&mut self, mut f: F)
@@ -45,15 +69,208 @@ where
             if f(key) {
                 true
             } else {
-                self.indirect_heap_memory -= T::heap_size(key);
+                self.tally.remove(ElementSize::of(key));
                 false
             }
         });
+        self.maybe_shrink();
+        self.after_op("retain");
+    }
+
+    /// Clears the set and returns all removed elements. Like
+    /// [`HashSet::drain`], the set is empty afterwards even if the iterator
+    /// is dropped early.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        self.tally.clear();
+        self.after_op("drain");
+        self.inner.drain()
+    }
+
+    /// Removes and yields all elements for which `pred` returns `true`.
+    /// Elements that are not yielded stay in the set and remain counted.
+    /// The shrink policy is applied once the iterator is dropped.
+    ///
+    /// Unlike [`HashSet::extract_if`], `pred` is called on all elements up
+    /// front, as the iterator needs the set itself to put back what it did
+    /// not yield.
+    pub fn extract_if<F>(&mut self, pred: F) -> TrackedExtractIf<'_, T, S>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let extracted: Vec<T> = self.inner.extract_if(pred).collect();
+        TrackedExtractIf {
+            extracted: extracted.into_iter(),
+            set: self,
+        }
+    }
+
+    /// Returns a reference to the value equal to `key`, inserting the value
+    /// computed by `f` if there is none.
+    ///
+    /// # Panics
+    ///
+    /// If the value computed by `f` is not equal to `key`.
+    pub fn get_or_insert_with<Q, F>(&mut self, key: &Q, f: F) -> &T
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&Q) -> T,
+    {
+        if !self.inner.contains(key) {
+            let value = f(key);
+            assert!(value.borrow() == key, "new value is not equal to the key");
+            self.tally.add(ElementSize::of(&value));
+            self.inner.insert(value);
+            self.after_op("get_or_insert_with");
+        }
+        self.inner
+            .get(key)
+            .expect("value is present after insertion")
+    }
+}
+
+impl<T, S> Tracked<HashSet<T, S>>
+where
+    T: Eq + Hash + HeapSize + Clone,
+    S: BuildHasher + Default,
+{
+    /// Builds a tracked set of all values in `self` or `other`.
+    #[must_use]
+    pub fn union_tracked(&self, other: &HashSet<T, S>) -> Self {
+        self.inner.union(other).cloned().collect()
+    }
+
+    /// Builds a tracked set of all values in both `self` and `other`.
+    #[must_use]
+    pub fn intersection_tracked(&self, other: &HashSet<T, S>) -> Self {
+        self.inner.intersection(other).cloned().collect()
+    }
+
+    /// Builds a tracked set of all values in `self` but not in `other`.
+    #[must_use]
+    pub fn difference_tracked(&self, other: &HashSet<T, S>) -> Self {
+        self.inner.difference(other).cloned().collect()
+    }
+
+    /// Builds a tracked set of all values in exactly one of `self` and
+    /// `other`.
+    #[must_use]
+    pub fn symmetric_difference_tracked(&self, other: &HashSet<T, S>) -> Self {
+        self.inner.symmetric_difference(other).cloned().collect()
+    }
+}
+
+impl<T> Tracked<HashSet<T, RandomState>> {
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_tally(HashSet::with_capacity(capacity), 0)
+    }
+}
+
+impl<T, S> Tracked<HashSet<T, S>> {
+    #[must_use]
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_tally(HashSet::with_hasher(hasher), 0)
+    }
+
+    #[must_use]
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_tally(HashSet::with_capacity_and_hasher(capacity, hasher), 0)
+    }
+}
+
+/// Values equal to one already in the set are dropped, like
+/// [`Tracked::insert`] does.
+impl<T, S> Extend<T> for Tracked<HashSet<T, S>>
+where
+    T: Eq + Hash + HeapSize,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        // Reserve like `HashSet::extend`, which assumes that duplicates are
+        // common once the set is not empty.
+        let additional = if self.inner.is_empty() {
+            iter.size_hint().0
+        } else {
+            iter.size_hint().0.div_ceil(2)
+        };
+        self.inner.reserve(additional);
+        for key in iter {
+            let key_size = ElementSize::of(&key);
+            if self.inner.insert(key) {
+                self.tally.add(key_size);
+            }
+        }
+        self.after_op("extend");
+    }
+}
+
+impl<T, S> FromIterator<T> for Tracked<HashSet<T, S>>
+where
+    T: Eq + Hash + HeapSize,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::with_hasher(S::default());
+        set.extend(iter);
+        set
     }
 }
 
 impl_new!(HashSet<T, S>, S: BuildHasher + Default);
-impl_clear!(HashSet<T, S>);
+impl_clear!(HashSet<T, S>, capacity);
 impl_from!(HashSet<T, S>, |v| T::heap_size(v), T);
+impl_capacity!(HashSet<T, S>, T: Eq + Hash, S: BuildHasher);
+impl_shrink!(HashSet<T, S>, T: Eq + Hash, S: BuildHasher);
 impl_shallow_heap_size!(HashSet<T, S>, |v: &Self| v.capacity()
     * (size_of::<T>() + size_of::<usize>()));
+
+/// Iterator returned by [`Tracked::extract_if`] on a `HashSet`.
+pub struct TrackedExtractIf<'a, T, S>
+where
+    T: Eq + Hash + HeapSize,
+    S: BuildHasher,
+{
+    // Taken out of the set, but still counted until yielded.
+    extracted: vec::IntoIter<T>,
+    set: &'a mut Tracked<HashSet<T, S>>,
+}
+
+impl<T, S> Iterator for TrackedExtractIf<'_, T, S>
+where
+    T: Eq + Hash + HeapSize,
+    S: BuildHasher,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.extracted.next()?;
+        self.set.tally.remove(ElementSize::of(&key));
+        Some(key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.extracted.size_hint()
+    }
+}
+
+impl<T, S> FusedIterator for TrackedExtractIf<'_, T, S>
+where
+    T: Eq + Hash + HeapSize,
+    S: BuildHasher,
+{
+}
+
+impl<T, S> Drop for TrackedExtractIf<'_, T, S>
+where
+    T: Eq + Hash + HeapSize,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        // Like `HashSet::extract_if`, keep what was not yielded.
+        self.set.inner.extend(self.extracted.by_ref());
+        self.set.maybe_shrink();
+        self.set.after_op("extract_if");
+    }
+}
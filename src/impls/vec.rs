@@ -1,9 +1,13 @@
-use std::iter;
+use std::{cell::Cell, iter};
 
 use crate::{
     HeapSize, Tracked,
-    macros::{impl_clear, impl_from, impl_new, impl_shallow_heap_size},
-    tracked_value::TrackedValue,
+    macros::{
+        impl_capacity, impl_clear, impl_extend, impl_from, impl_new, impl_permutations,
+        impl_reserve_exact, impl_shallow_heap_size, impl_shrink,
+    },
+    tally::{ElementSize, Tally},
+    tracked_value::{TrackedBatch, TrackedValue},
 };
 
 impl<T> Tracked<Vec<T>>
@@ -11,13 +15,16 @@ where
     T: HeapSize,
 {
     pub fn push(&mut self, value: T) {
-        self.indirect_heap_memory += T::heap_size(&value);
+        self.tally.add(ElementSize::of(&value));
         self.inner.push(value);
+        self.after_op("push");
     }
 
     pub fn pop(&mut self) -> Option<T> {
         if let Some(value) = self.inner.pop() {
-            self.indirect_heap_memory -= T::heap_size(&value);
+            self.tally.remove(ElementSize::of(&value));
+            self.maybe_shrink();
+            self.after_op("pop");
             Some(value)
         } else {
             None
@@ -25,13 +32,16 @@ where
     }
 
     pub fn insert(&mut self, index: usize, value: T) {
-        self.indirect_heap_memory += T::heap_size(&value);
+        self.tally.add(ElementSize::of(&value));
         self.inner.insert(index, value);
+        self.after_op("insert");
     }
 
     pub fn remove(&mut self, index: usize) -> T {
         let value = self.inner.remove(index);
-        self.indirect_heap_memory -= T::heap_size(&value);
+        self.tally.remove(ElementSize::of(&value));
+        self.maybe_shrink();
+        self.after_op("remove");
         value
     }
 
@@ -43,10 +53,12 @@ where
             if f(v) {
                 true
             } else {
-                self.indirect_heap_memory -= T::heap_size(v);
+                self.tally.remove(ElementSize::of(v));
                 false
             }
         });
+        self.maybe_shrink();
+        self.after_op("retain");
     }
 
     pub fn resize_with<F>(&mut self, new_len: usize, mut f: F)
@@ -58,11 +70,12 @@ where
             self.inner.extend(
                 iter::repeat_with(|| {
                     let val = f();
-                    self.indirect_heap_memory += T::heap_size(&val);
+                    self.tally.add(ElementSize::of(&val));
                     val
                 })
                 .take(new_len - len),
             );
+            self.after_op("resize_with");
         } else {
             self.truncate(new_len);
         }
@@ -73,34 +86,85 @@ where
             return;
         }
         for val in &self.inner[new_len..] {
-            self.indirect_heap_memory -= T::heap_size(val);
+            self.tally.remove(ElementSize::of(val));
         }
         self.inner.truncate(new_len);
+        self.maybe_shrink();
+        self.after_op("truncate");
     }
 
     /// Consider using [`append_tracked(...)`].
     pub fn append(&mut self, other: &mut Vec<T>) {
         for elem in &*other {
-            self.indirect_heap_memory += elem.heap_size();
+            self.tally.add(ElementSize::of(elem));
         }
         self.inner.append(other);
+        self.after_op("append");
     }
 
     pub fn append_tracked(&mut self, other: &mut Self) {
-        self.indirect_heap_memory += other.indirect_heap_memory;
+        self.absorb_tally(other);
         self.inner.append(&mut other.inner);
+        self.after_op("append");
+        other.after_op("append");
     }
 
     pub fn swap_remove(&mut self, index: usize) -> T {
         let value = self.inner.swap_remove(index);
-        self.indirect_heap_memory -= T::heap_size(&value);
+        self.tally.remove(ElementSize::of(&value));
+        self.maybe_shrink();
+        self.after_op("swap_remove");
         value
     }
 
-    pub fn get_mut(&mut self, index: usize) -> Option<TrackedValue<'_, T>> {
+    pub fn get_mut(&mut self, index: usize) -> Option<TrackedValue<'_, T, &mut Tally<Vec<T>>>> {
         self.inner
             .get_mut(index)
-            .map(|v| TrackedValue::new(&mut self.indirect_heap_memory, v))
+            .map(|v| TrackedValue::new(&mut self.tally, v))
+    }
+
+    /// Iterates mutably over the elements. Each element is measured again
+    /// when its guard is dropped.
+    pub fn iter_mut_tracked(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = TrackedValue<'_, T, &Cell<Tally<Vec<T>>>>> + ExactSizeIterator
+    {
+        let tally = Cell::from_mut(&mut self.tally);
+        self.inner
+            .iter_mut()
+            .map(move |v| TrackedValue::new(tally, v))
+    }
+
+    /// Applies `f` to every element. Unlike [`Self::iter_mut_tracked`], no
+    /// guard is created per element, which is cheaper for bulk updates.
+    pub fn for_each_mut_batched<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut T),
+    {
+        let mut batch = TrackedBatch::new(&mut self.tally);
+        for v in &mut self.inner {
+            batch.update(v, &mut f);
+        }
+        self.after_op("for_each_mut_batched");
+    }
+}
+
+impl<T> Tracked<Vec<T>> {
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_tally(Vec::with_capacity(capacity), 0)
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.inner.swap(a, b);
+    }
+
+    pub fn rotate_left(&mut self, mid: usize) {
+        self.inner.rotate_left(mid);
+    }
+
+    pub fn rotate_right(&mut self, k: usize) {
+        self.inner.rotate_right(k);
     }
 }
 
@@ -112,8 +176,9 @@ where
         let len = self.inner.len();
         if new_len > len {
             let n = new_len - len;
-            self.indirect_heap_memory += value.heap_size() * n;
+            self.tally.add_many(ElementSize::of(&value), n);
             self.inner.extend(iter::repeat_n(value, n));
+            self.after_op("resize");
         } else {
             self.truncate(new_len);
         }
@@ -121,6 +186,11 @@ where
 }
 
 impl_new!(Vec<T>);
-impl_clear!(Vec<T>);
+impl_clear!(Vec<T>, capacity);
 impl_from!(Vec<T>, |v| T::heap_size(v));
+impl_extend!(Vec<T>, T);
+impl_permutations!(Vec<T>, Vec::as_mut_slice);
+impl_capacity!(Vec<T>);
+impl_reserve_exact!(Vec<T>);
+impl_shrink!(Vec<T>);
 impl_shallow_heap_size!(Vec<T>, |v: &Self| v.capacity() * (size_of::<T>()));
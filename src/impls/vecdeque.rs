@@ -1,10 +1,15 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
 use std::iter;
 
 use crate::{
     HeapSize, Tracked,
-    macros::{impl_clear, impl_from, impl_new, impl_shallow_heap_size},
-    tracked_value::TrackedValue,
+    macros::{
+        impl_capacity, impl_clear, impl_extend, impl_from, impl_new, impl_permutations,
+        impl_reserve_exact, impl_shallow_heap_size, impl_shrink,
+    },
+    tally::{ElementSize, Tally},
+    tracked_value::{TrackedBatch, TrackedValue},
 };
 
 impl<T> Tracked<VecDeque<T>>
@@ -12,36 +17,51 @@ where
     T: HeapSize,
 {
     pub fn push_back(&mut self, value: T) {
-        self.indirect_heap_memory += T::heap_size(&value);
+        self.tally.add(ElementSize::of(&value));
         self.inner.push_back(value);
+        self.after_op("push_back");
     }
 
     pub fn pop_back(&mut self) -> Option<T> {
-        self.inner
+        let value = self
+            .inner
             .pop_back()
-            .inspect(|v| self.indirect_heap_memory -= T::heap_size(v))
+            .inspect(|v| self.tally.remove(ElementSize::of(v)));
+        self.maybe_shrink();
+        self.after_op("pop_back");
+        value
     }
 
     pub fn push_front(&mut self, value: T) {
-        self.indirect_heap_memory += T::heap_size(&value);
+        self.tally.add(ElementSize::of(&value));
         self.inner.push_front(value);
+        self.after_op("push_front");
     }
 
     pub fn pop_front(&mut self) -> Option<T> {
-        self.inner
+        let value = self
+            .inner
             .pop_front()
-            .inspect(|v| self.indirect_heap_memory -= T::heap_size(v))
+            .inspect(|v| self.tally.remove(ElementSize::of(v)));
+        self.maybe_shrink();
+        self.after_op("pop_front");
+        value
     }
 
     pub fn insert(&mut self, index: usize, value: T) {
-        self.indirect_heap_memory += T::heap_size(&value);
+        self.tally.add(ElementSize::of(&value));
         self.inner.insert(index, value);
+        self.after_op("insert");
     }
 
     pub fn remove(&mut self, index: usize) -> Option<T> {
-        self.inner
+        let value = self
+            .inner
             .remove(index)
-            .inspect(|value| self.indirect_heap_memory -= T::heap_size(value))
+            .inspect(|value| self.tally.remove(ElementSize::of(value)));
+        self.maybe_shrink();
+        self.after_op("remove");
+        value
     }
 
     pub fn retain<F>(&mut self, mut f: F)
@@ -52,10 +72,12 @@ where
             if f(v) {
                 true
             } else {
-                self.indirect_heap_memory -= T::heap_size(v);
+                self.tally.remove(ElementSize::of(v));
                 false
             }
         });
+        self.maybe_shrink();
+        self.after_op("retain");
     }
 
     pub fn resize_with<F>(&mut self, new_len: usize, mut f: F)
@@ -67,11 +89,12 @@ where
             self.inner.extend(
                 iter::repeat_with(|| {
                     let val = f();
-                    self.indirect_heap_memory += T::heap_size(&val);
+                    self.tally.add(ElementSize::of(&val));
                     val
                 })
                 .take(new_len - len),
             );
+            self.after_op("resize_with");
         } else {
             self.truncate(new_len);
         }
@@ -82,39 +105,99 @@ where
             return;
         }
         for val in &self.inner.split_off(new_len) {
-            self.indirect_heap_memory -= T::heap_size(val);
+            self.tally.remove(ElementSize::of(val));
         }
+        self.maybe_shrink();
+        self.after_op("truncate");
     }
 
     /// Consider using [`append_tracked(...)`].
     pub fn append(&mut self, other: &mut VecDeque<T>) {
         for elem in &*other {
-            self.indirect_heap_memory += elem.heap_size();
+            self.tally.add(ElementSize::of(elem));
         }
         self.inner.append(other);
+        self.after_op("append");
     }
 
     pub fn append_tracked(&mut self, other: &mut Self) {
-        self.indirect_heap_memory += other.indirect_heap_memory;
+        self.absorb_tally(other);
         self.inner.append(&mut other.inner);
+        self.after_op("append");
+        other.after_op("append");
     }
 
     pub fn swap_remove_back(&mut self, index: usize) -> Option<T> {
-        self.inner
+        let value = self
+            .inner
             .swap_remove_back(index)
-            .inspect(|value| self.indirect_heap_memory -= T::heap_size(value))
+            .inspect(|value| self.tally.remove(ElementSize::of(value)));
+        self.maybe_shrink();
+        self.after_op("swap_remove_back");
+        value
     }
 
     pub fn swap_remove_front(&mut self, index: usize) -> Option<T> {
-        self.inner
+        let value = self
+            .inner
             .swap_remove_front(index)
-            .inspect(|value| self.indirect_heap_memory -= T::heap_size(value))
+            .inspect(|value| self.tally.remove(ElementSize::of(value)));
+        self.maybe_shrink();
+        self.after_op("swap_remove_front");
+        value
     }
 
-    pub fn get_mut(&mut self, index: usize) -> Option<TrackedValue<'_, T>> {
+    pub fn get_mut(
+        &mut self,
+        index: usize,
+    ) -> Option<TrackedValue<'_, T, &mut Tally<VecDeque<T>>>> {
         self.inner
             .get_mut(index)
-            .map(|v| TrackedValue::new(&mut self.indirect_heap_memory, v))
+            .map(|v| TrackedValue::new(&mut self.tally, v))
+    }
+
+    /// Iterates mutably over the elements. Each element is measured again
+    /// when its guard is dropped.
+    pub fn iter_mut_tracked(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = TrackedValue<'_, T, &Cell<Tally<VecDeque<T>>>>>
+    + ExactSizeIterator {
+        let tally = Cell::from_mut(&mut self.tally);
+        self.inner
+            .iter_mut()
+            .map(move |v| TrackedValue::new(tally, v))
+    }
+
+    /// Applies `f` to every element. Unlike [`Self::iter_mut_tracked`], no
+    /// guard is created per element, which is cheaper for bulk updates.
+    pub fn for_each_mut_batched<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut T),
+    {
+        let mut batch = TrackedBatch::new(&mut self.tally);
+        for v in &mut self.inner {
+            batch.update(v, &mut f);
+        }
+        self.after_op("for_each_mut_batched");
+    }
+}
+
+impl<T> Tracked<VecDeque<T>> {
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_tally(VecDeque::with_capacity(capacity), 0)
+    }
+
+    pub fn swap(&mut self, i: usize, j: usize) {
+        self.inner.swap(i, j);
+    }
+
+    pub fn rotate_left(&mut self, n: usize) {
+        self.inner.rotate_left(n);
+    }
+
+    pub fn rotate_right(&mut self, n: usize) {
+        self.inner.rotate_right(n);
     }
 }
 
@@ -126,8 +209,9 @@ where
         let len = self.inner.len();
         if new_len > len {
             let n = new_len - len;
-            self.indirect_heap_memory += value.heap_size() * n;
+            self.tally.add_many(ElementSize::of(&value), n);
             self.inner.extend(iter::repeat_n(value, n));
+            self.after_op("resize");
         } else {
             self.truncate(new_len);
         }
@@ -135,6 +219,11 @@ where
 }
 
 impl_new!(VecDeque<T>);
-impl_clear!(VecDeque<T>);
+impl_clear!(VecDeque<T>, capacity);
 impl_from!(VecDeque<T>, |v| T::heap_size(v));
+impl_extend!(VecDeque<T>, T);
+impl_permutations!(VecDeque<T>, VecDeque::make_contiguous);
+impl_capacity!(VecDeque<T>);
+impl_reserve_exact!(VecDeque<T>);
+impl_shrink!(VecDeque<T>);
 impl_shallow_heap_size!(VecDeque<T>, |v: &Self| v.capacity() * (size_of::<T>()));
@@ -0,0 +1,219 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, btree_map::Entry},
+    fmt,
+    hash::Hash,
+    marker::PhantomData,
+};
+
+use crate::{HeapSize, Tracked};
+
+/// Collections whose elements have a key, which the index of the largest
+/// entries is by. Public only because the guards of map values name it; the
+/// module is private.
+pub trait MapKey {
+    type Key;
+}
+
+impl<K, V, S> MapKey for HashMap<K, V, S> {
+    type Key = K;
+}
+
+impl<K, V> MapKey for BTreeMap<K, V> {
+    type Key = K;
+}
+
+/// Index of the keys of map `C` by the heap size of their values. It is a
+/// trait object, so that [`crate::tally::Tally`] can hold it without the
+/// bounds it needs, which only maps that enable it have to meet.
+pub(crate) trait KeyIndex<C>: Send + Sync {
+    fn insert(&mut self, key: &C::Key, size: usize)
+    where
+        C: MapKey;
+    fn remove(&mut self, key: &C::Key, size: usize)
+    where
+        C: MapKey;
+    fn clear(&mut self);
+    fn clone_key(&self, key: &C::Key) -> C::Key
+    where
+        C: MapKey;
+    /// The keys of the `n` largest values, largest first.
+    fn largest(&self, n: usize) -> Vec<&C::Key>
+    where
+        C: MapKey;
+}
+
+impl<C> fmt::Debug for dyn KeyIndex<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyIndex")
+    }
+}
+
+/// A set of keys that have the same size.
+pub(crate) trait KeySet<K: 'static>: Default + Send + Sync + 'static {
+    fn insert(&mut self, key: K);
+    fn remove(&mut self, key: &K);
+    fn is_empty(&self) -> bool;
+    fn keys(&self) -> impl Iterator<Item = &K>;
+}
+
+impl<K: Eq + Hash + Send + Sync + 'static> KeySet<K> for HashSet<K> {
+    fn insert(&mut self, key: K) {
+        HashSet::insert(self, key);
+    }
+
+    fn remove(&mut self, key: &K) {
+        HashSet::remove(self, key);
+    }
+
+    fn is_empty(&self) -> bool {
+        HashSet::is_empty(self)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Send + Sync + 'static> KeySet<K> for BTreeSet<K> {
+    fn insert(&mut self, key: K) {
+        BTreeSet::insert(self, key);
+    }
+
+    fn remove(&mut self, key: &K) {
+        BTreeSet::remove(self, key);
+    }
+
+    fn is_empty(&self) -> bool {
+        BTreeSet::is_empty(self)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter()
+    }
+}
+
+pub(crate) struct LargestIndex<K, S> {
+    by_size: BTreeMap<usize, S>,
+    _key: PhantomData<fn() -> K>,
+}
+
+impl<C, K, S> KeyIndex<C> for LargestIndex<K, S>
+where
+    C: MapKey<Key = K>,
+    K: Clone + 'static,
+    S: KeySet<K>,
+{
+    fn insert(&mut self, key: &K, size: usize) {
+        self.by_size.entry(size).or_default().insert(key.clone());
+    }
+
+    fn remove(&mut self, key: &K, size: usize) {
+        if let Entry::Occupied(mut keys) = self.by_size.entry(size) {
+            keys.get_mut().remove(key);
+            if keys.get().is_empty() {
+                keys.remove();
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_size.clear();
+    }
+
+    fn clone_key(&self, key: &K) -> K {
+        key.clone()
+    }
+
+    fn largest(&self, n: usize) -> Vec<&K> {
+        self.by_size
+            .values()
+            .rev()
+            .flat_map(KeySet::keys)
+            .take(n)
+            .collect()
+    }
+}
+
+// Maps implement their public methods on top of these, choosing the key set.
+impl<C> Tracked<C>
+where
+    C: MapKey,
+{
+    pub(crate) fn enable_largest_with<K, V, S>(&mut self)
+    where
+        C: MapKey<Key = K>,
+        for<'a> &'a C: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Clone + 'static,
+        V: HeapSize,
+        S: KeySet<K>,
+    {
+        if !self.tally.largest_enabled() {
+            let index = LargestIndex::<K, S> {
+                by_size: BTreeMap::new(),
+                _key: PhantomData,
+            };
+            self.tally.set_largest(Some(Box::new(index)));
+            self.sync_largest();
+        }
+    }
+
+    /// Rebuilds the index of the largest entries if an operation that does
+    /// not know about keys left it stale.
+    pub(crate) fn sync_largest<K, V>(&mut self)
+    where
+        C: MapKey<Key = K>,
+        for<'a> &'a C: IntoIterator<Item = (&'a K, &'a V)>,
+        V: HeapSize,
+    {
+        let inner = &self.inner;
+        self.tally
+            .rebuild_largest(|| inner.into_iter().map(|(k, v)| (k, V::heap_size(v))));
+    }
+
+    pub(crate) fn largest_with<'a, K, V>(&'a self, n: usize) -> Vec<&'a K>
+    where
+        C: MapKey<Key = K>,
+        &'a C: IntoIterator<Item = (&'a K, &'a V)>,
+        V: HeapSize + 'a,
+    {
+        if let Some(index) = self.tally.largest() {
+            return index.largest(n);
+        }
+        // Without an up-to-date index, keep the `n` largest in a min-heap.
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        for (k, v) in &self.inner {
+            heap.push(Reverse(BySize(V::heap_size(v), k)));
+            if heap.len() > n {
+                heap.pop();
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(BySize(_, k))| k)
+            .collect()
+    }
+}
+
+/// A key ordered by the size of its value only.
+struct BySize<'a, K>(usize, &'a K);
+
+impl<K> PartialEq for BySize<'_, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K> Eq for BySize<'_, K> {}
+
+impl<K> PartialOrd for BySize<'_, K> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Ord for BySize<'_, K> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
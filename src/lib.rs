@@ -1,14 +1,48 @@
+#[cfg(feature = "metrics")]
+mod gauge;
+mod histogram;
 mod impls;
+mod largest;
 mod macros;
+pub mod openmetrics;
+#[cfg(feature = "rayon")]
+pub mod par;
+mod peak;
+pub mod registry;
+mod sample;
+pub mod shared;
+mod shrink;
+mod snapshot;
+mod tally;
+#[cfg(feature = "tracing")]
+pub mod trace;
 mod tracked_value;
+mod tree;
 
-#[derive(Default, Debug, Eq)]
+pub use histogram::{SizeBucket, SizeHistogram};
+#[cfg(feature = "derive")]
+pub use memtally_derive::HeapTree;
+pub use peak::PeakUsage;
+use shared::SharedAllocation;
+pub use shrink::ShrinkPolicy;
+pub use snapshot::{Change, ChangeKind, MemorySnapshot, SnapshotDiff};
+use tally::{ElementSize, Tally};
+pub use tree::{HeapTree, MemoryTree};
+
+#[derive(Default, Debug)]
 pub struct Tracked<T> {
     inner: T,
-    indirect_heap_memory: usize,
+    tally: Tally<T>,
 }
 
 impl<T> Tracked<T> {
+    pub(crate) fn with_tally(inner: T, indirect_heap_memory: usize) -> Self {
+        Self {
+            inner,
+            tally: Tally::new(indirect_heap_memory, 0),
+        }
+    }
+
     /// Get the underlying collection. This discards the memory counter.
     pub fn into_inner(self) -> T {
         self.inner
@@ -18,6 +52,100 @@ impl<T> Tracked<T> {
     pub fn inner(&self) -> &T {
         &self.inner
     }
+
+    /// Get the underlying collection together with the summed heap size of
+    /// its elements, e.g. to persist it and later restore the collection via
+    /// [`Tracked::from_parts_unchecked`]. For maps, the split between keys
+    /// and values is discarded, see `into_parts_with_keys` to keep it.
+    pub fn into_parts(self) -> (T, usize) {
+        (self.inner, self.tally.indirect())
+    }
+
+    /// Converts the underlying collection with `f`, keeping the tally. `f`
+    /// must neither add, remove nor change any element.
+    pub(crate) fn map_inner<U>(self, f: impl FnOnce(T) -> U) -> Tracked<U> {
+        Tracked {
+            inner: f(self.inner),
+            tally: self.tally.convert(),
+        }
+    }
+}
+
+impl<C: IndirectHeapSize + ShallowHeapSize> Tracked<C> {
+    /// Wrap a collection whose elements are known to allocate
+    /// `indirect_heap_memory` bytes, without walking them. In debug builds,
+    /// the claim is verified by a recount.
+    ///
+    /// # Safety
+    ///
+    /// `indirect_heap_memory` must be exactly the summed heap size of the
+    /// elements, as [`Tracked::into_parts`] returns it. A wrong value does
+    /// not cause undefined behavior within this crate, but all reported sizes
+    /// will be off, and removals may panic on underflow.
+    ///
+    /// For maps, the keys are walked to split `indirect_heap_memory` between
+    /// keys and values, see `from_parts_with_keys_unchecked` to restore the
+    /// split without that.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, if `indirect_heap_memory` is wrong.
+    pub unsafe fn from_parts_unchecked(inner: C, indirect_heap_memory: usize) -> Self {
+        debug_assert_eq!(
+            inner.indirect_heap_size(),
+            indirect_heap_memory,
+            "from_parts_unchecked was given a wrong heap size"
+        );
+        // Only maps have to walk their keys here, and only `SharedOnce`
+        // elements report shared allocations.
+        let key_heap_memory = inner.indirect_key_heap_size();
+        let mut tally = Tally::new(indirect_heap_memory, key_heap_memory);
+        tally.restore_shared(inner.shared_allocations());
+        Self {
+            tally,
+            ..Self::with_tally(inner, 0)
+        }
+    }
+
+    /// Get mutable access to the underlying collection, e.g. for methods that
+    /// are not mirrored by `Tracked`, or to extend containers of other
+    /// crates, which `Tracked` does not implement `Extend` for. The tally is
+    /// recomputed afterwards, which walks all elements.
+    pub fn with_inner_mut<R>(&mut self, f: impl FnOnce(&mut C) -> R) -> R {
+        let result = f(&mut self.inner);
+        self.recount();
+        self.after_op("with_inner_mut");
+        result
+    }
+
+    /// Get mutable access to the underlying collection for operations that
+    /// do not change the heap size of any element, like sorting or swapping.
+    /// The tally is kept as is. In debug builds, this is checked by a
+    /// recount, unless the tally is an estimate.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, if the elements' heap size changed.
+    pub fn with_inner_mut_unchanged<R>(&mut self, f: impl FnOnce(&mut C) -> R) -> R {
+        let result = f(&mut self.inner);
+        debug_assert!(
+            self.tally.estimate().is_some()
+                || self.inner.indirect_heap_size() == self.tally.indirect(),
+            "with_inner_mut_unchanged must not change the heap size of the elements"
+        );
+        self.after_op("with_inner_mut_unchanged");
+        result
+    }
+
+    /// Recompute the tally by walking all elements.
+    pub(crate) fn recount(&mut self) {
+        let inner = &self.inner;
+        self.tally.reset(
+            inner.element_heap_sizes(),
+            inner.indirect_key_heap_size(),
+            inner.shared_allocations(),
+        );
+    }
 }
 
 impl<C> std::ops::Deref for Tracked<C> {
@@ -41,6 +169,8 @@ impl<C: PartialEq> PartialEq for Tracked<C> {
     }
 }
 
+impl<C: Eq> Eq for Tracked<C> {}
+
 impl<C: PartialOrd> PartialOrd for Tracked<C> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.inner.partial_cmp(&other.inner)
@@ -55,45 +185,115 @@ impl<C: Ord> Ord for Tracked<C> {
 
 // Creation
 
-impl<C, T: HeapSize> FromIterator<T> for Tracked<C>
+impl<C, T> Clone for Tracked<C>
 where
-    C: FromIterator<T>,
+    C: Clone,
+    for<'a> &'a C: IntoIterator<Item = &'a T>,
+    T: HeapSize,
 {
-    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
-        let mut indirect_heap_memory = 0;
-        let inner: C = iter
-            .into_iter()
-            .inspect(|v| indirect_heap_memory += T::heap_size(v))
-            .collect();
-        Self {
-            inner,
-            indirect_heap_memory,
+    /// Recounts the clone. The size histogram and shrink policy carry over,
+    /// peak tracking does not.
+    fn clone(&self) -> Self {
+        let mut clone = Self::with_tally(self.inner.clone(), 0);
+        clone
+            .tally
+            .set_histogram(self.tally.histogram().map(|_| SizeHistogram::new()));
+        for v in &clone.inner {
+            clone.tally.add(ElementSize::of(v));
         }
+        clone.tally.set_shrink_policy(self.tally.shrink_policy());
+        clone
     }
 }
 
-impl<C: Extend<T>, T: HeapSize> Extend<T> for Tracked<C> {
-    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
-        self.inner.extend(
-            iter.into_iter()
-                .inspect(|v| self.indirect_heap_memory += T::heap_size(v)),
-        );
+/// Common interface of all `Tracked` collections, for code that reports,
+/// clears or budgets collections regardless of their type.
+pub trait TrackedCollection {
+    #[must_use]
+    fn len(&self) -> usize;
+
+    #[must_use]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of elements the collection can hold without reallocating, or
+    /// `None` for collections without a capacity, like `BTreeMap`. Named so
+    /// that it does not shadow the collection's own `capacity`.
+    #[must_use]
+    fn tracked_capacity(&self) -> Option<usize>;
+
+    /// Total heap usage, i.e. [`Self::shallow_size`] plus
+    /// [`Self::indirect_size`].
+    #[must_use]
+    fn heap_size(&self) -> usize {
+        self.shallow_size() + self.indirect_size()
+    }
+
+    /// What the collection allocates itself, see [`ShallowHeapSize`].
+    #[must_use]
+    fn shallow_size(&self) -> usize;
+
+    /// What the elements allocate, as tracked.
+    #[must_use]
+    fn indirect_size(&self) -> usize;
+
+    fn clear(&mut self);
+
+    /// Recount what the elements allocate by walking all of them. Only
+    /// needed if the tally went off, e.g. because elements were changed
+    /// through interior mutability.
+    fn recalculate(&mut self);
+}
+
+/// Used for the parts of a container's API that [`TrackedCollection`]
+/// forwards. Together with [`ShallowHeapSize`] and [`IndirectHeapSize`], this
+/// makes `Tracked` wrappers of downstream containers a `TrackedCollection`.
+pub trait Collection {
+    #[must_use]
+    fn len(&self) -> usize;
+
+    #[must_use]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
+
+    /// See [`TrackedCollection::tracked_capacity`].
+    #[must_use]
+    fn capacity(&self) -> Option<usize>;
+
+    fn clear(&mut self);
 }
 
-impl<C, T> Clone for Tracked<C>
+impl<C> TrackedCollection for Tracked<C>
 where
-    C: Clone,
-    for<'a> &'a C: IntoIterator<Item = &'a T>,
-    T: HeapSize,
+    C: Collection + ShallowHeapSize + IndirectHeapSize,
 {
-    fn clone(&self) -> Self {
-        let inner = self.inner.clone();
-        let indirect_heap_memory = (&inner).into_iter().map(|v| T::heap_size(v)).sum();
-        Self {
-            inner,
-            indirect_heap_memory,
-        }
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn tracked_capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
+
+    fn shallow_size(&self) -> usize {
+        self.inner.shallow_heap_size()
+    }
+
+    fn indirect_size(&self) -> usize {
+        self.tally.indirect()
+    }
+
+    fn clear(&mut self) {
+        self.tally.clear();
+        self.inner.clear();
+        self.after_op("clear");
+    }
+
+    fn recalculate(&mut self) {
+        self.recount();
+        self.after_op("recalculate");
     }
 }
 
@@ -101,17 +301,76 @@ where
 /// their elements. For example, a `Vec<T>` with capacity 16 allocates `16 *
 /// std::mem::size_of::<T>()` directly. But whatever the elements of type `T`
 /// might allocate is not considered here.
-trait ShallowHeapSize {
+pub trait ShallowHeapSize {
     #[must_use]
     fn shallow_heap_size(&self) -> usize;
 }
 
+/// Used for containers to report what their elements allocate, which is
+/// exactly the part that [`ShallowHeapSize`] leaves out. Computing this walks
+/// all elements, which [`Tracked`] only does when it has to.
+pub trait IndirectHeapSize {
+    /// The heap size of every element. For maps, only the values are
+    /// measured, see [`Self::indirect_key_heap_size`] for the keys.
+    fn element_heap_sizes(&self) -> impl Iterator<Item = usize>;
+
+    #[must_use]
+    fn indirect_heap_size(&self) -> usize {
+        self.element_heap_sizes().sum::<usize>()
+            + self.indirect_key_heap_size()
+            + shared::once_size(self.shared_allocations())
+    }
+
+    /// For maps, the part of [`Self::indirect_heap_size`] that is allocated
+    /// by the keys.
+    #[must_use]
+    fn indirect_key_heap_size(&self) -> usize {
+        0
+    }
+
+    /// The heap sizes of the elements at the ascending `indices` in
+    /// iteration order, each paired with the heap size of its key for maps,
+    /// or zero otherwise. Used to estimate the tally from a sample, see
+    /// `Tracked::from_sampled`.
+    ///
+    /// The default walks [`Self::element_heap_sizes`] and thus measures all
+    /// elements up to the last index. Maps must override it to measure keys.
+    fn sampled_heap_sizes(&self, indices: &[usize]) -> impl Iterator<Item = (usize, usize)> {
+        crate::sample::pick(self.element_heap_sizes(), indices).map(|size| (size, 0))
+    }
+
+    /// The allocation shared by every element, or value for maps, that has
+    /// one, see [`HeapSize::shared_allocation`]. Each is counted once in
+    /// [`Self::indirect_heap_size`].
+    fn shared_allocations(&self) -> impl Iterator<Item = SharedAllocation> {
+        std::iter::empty()
+    }
+}
+
 /// Used to query heap size of collection elements.
 pub trait HeapSize {
     #[must_use]
     fn heap_size(&self) -> usize;
+
+    /// An allocation that the element shares with other elements of the
+    /// same collection, which the collection counts once. It is not part of
+    /// [`Self::heap_size`]. See [`SharedOnce`](shared::SharedOnce).
+    #[must_use]
+    fn shared_allocation(&self) -> Option<SharedAllocation> {
+        None
+    }
 }
 
+/// Marks element types whose clones allocate exactly as much heap memory as
+/// the original, so that `Tracked::clone_exact` can copy the tally instead of
+/// recounting it. This does not hold for e.g. `String` and `Vec`, whose
+/// clones drop any spare capacity.
+///
+/// `x.clone().heap_size()` must equal `x.heap_size()` for every `x`.
+/// Otherwise, the sizes of clones will be off, and removals may panic on
+/// underflow. In debug builds, `clone_exact` verifies this by a recount.
+pub trait CloneHeapExact: HeapSize + Clone {}
+
 pub trait MemSize {
     #[must_use]
     fn mem_size(&self) -> usize;
@@ -129,7 +388,7 @@ pub use get_size;
 #[cfg(feature = "get-size")]
 impl<C: ShallowHeapSize> get_size::GetSize for Tracked<C> {
     fn get_heap_size(&self) -> usize {
-        self.inner.shallow_heap_size() + self.indirect_heap_memory
+        self.inner.shallow_heap_size() + self.tally.indirect()
     }
 }
 
@@ -138,7 +397,7 @@ pub use get_size2;
 #[cfg(feature = "get-size2")]
 impl<C: ShallowHeapSize> get_size2::GetSize for Tracked<C> {
     fn get_heap_size(&self) -> usize {
-        self.inner.shallow_heap_size() + self.indirect_heap_memory
+        self.inner.shallow_heap_size() + self.tally.indirect()
     }
 }
 
@@ -147,7 +406,7 @@ pub use memuse;
 #[cfg(feature = "memuse")]
 impl<C: ShallowHeapSize> memuse::DynamicUsage for Tracked<C> {
     fn dynamic_usage(&self) -> usize {
-        self.inner.shallow_heap_size() + self.indirect_heap_memory
+        self.inner.shallow_heap_size() + self.tally.indirect()
     }
 
     fn dynamic_usage_bounds(&self) -> (usize, Option<usize>) {
@@ -183,7 +442,7 @@ if #[cfg(feature = "get-size")] {
     // public api to all the bookkeeping we're doing.
     impl<C: ShallowHeapSize> HeapSize for Tracked<C> {
         fn heap_size(&self) -> usize {
-            self.inner.shallow_heap_size() + self.indirect_heap_memory
+            self.inner.shallow_heap_size() + self.tally.indirect()
         }
     }
 }}
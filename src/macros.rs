@@ -6,10 +6,7 @@ macro_rules! impl_new {
         {
             #[must_use]
             pub fn new() -> Self {
-                Self {
-                    inner: $name::default(),
-                    indirect_heap_memory: 0,
-                }
+                Self::with_tally($name::default(), 0)
             }
         }
     };
@@ -19,10 +16,7 @@ macro_rules! impl_new {
         {
             #[must_use]
             pub fn new() -> Self {
-                Self {
-                    inner: $name::<$($gen),*>::default(),
-                    indirect_heap_memory: 0,
-                }
+                Self::with_tally($name::<$($gen),*>::default(), 0)
             }
         }
     };
@@ -31,10 +25,32 @@ pub(crate) use impl_new;
 
 macro_rules! impl_clear {
     ($name:ident<$($gen:ident),*>) => {
+        impl_clear!(@impl $name<$($gen),*>, |_: &Self| None);
+    };
+    // For collections that report their capacity.
+    ($name:ident<$($gen:ident),*>, capacity) => {
+        impl_clear!(@impl $name<$($gen),*>, |v: &Self| Some(v.capacity()));
+    };
+    (@impl $name:ident<$($gen:ident),*>, $capacity:expr) => {
         impl<$($gen),*> Tracked<$name<$($gen),*>> {
             pub fn clear(&mut self) {
-                self.indirect_heap_memory = 0;
+                self.tally.clear();
                 self.inner.clear();
+                self.after_op("clear");
+            }
+        }
+
+        impl<$($gen),*> crate::Collection for $name<$($gen),*> {
+            fn len(&self) -> usize {
+                self.len()
+            }
+
+            fn capacity(&self) -> Option<usize> {
+                $capacity(self)
+            }
+
+            fn clear(&mut self) {
+                self.clear();
             }
         }
     };
@@ -46,14 +62,86 @@ macro_rules! impl_from {
         impl_from!($name<$($gen),*>, $fn, $($gen),*);
     };
     ($name:ident<$($gen:ident),*>, $fn:expr, $($bounds:ident),*) => {
+        impl<$($gen),*> crate::IndirectHeapSize for $name<$($gen),*>
+        where $($bounds: HeapSize),*
+        {
+            fn element_heap_sizes(&self) -> impl Iterator<Item = usize> {
+                self.iter().map($fn)
+            }
+
+            fn sampled_heap_sizes(&self, indices: &[usize]) -> impl Iterator<Item = (usize, usize)> {
+                crate::sample::pick(self.iter(), indices).map(|v| ($fn(v), 0))
+            }
+
+            fn shared_allocations(&self) -> impl Iterator<Item = crate::shared::SharedAllocation> {
+                self.iter().filter_map(|v| HeapSize::shared_allocation(v))
+            }
+        }
+
+        impl_from!(@from $name<$($gen),*>, $($bounds),*);
+    };
+    // For maps, `$fn` measures the values, and `$key_fn` the keys.
+    ($name:ident<$($gen:ident),*>, $fn:expr, keys: $key_fn:expr, $($bounds:ident),*) => {
+        impl<$($gen),*> crate::IndirectHeapSize for $name<$($gen),*>
+        where $($bounds: HeapSize),*
+        {
+            fn element_heap_sizes(&self) -> impl Iterator<Item = usize> {
+                self.values().map($fn)
+            }
+
+            fn indirect_key_heap_size(&self) -> usize {
+                self.keys().map($key_fn).sum()
+            }
+
+            fn sampled_heap_sizes(&self, indices: &[usize]) -> impl Iterator<Item = (usize, usize)> {
+                crate::sample::pick(self.iter(), indices).map(|(k, v)| ($fn(v), $key_fn(k)))
+            }
+
+            fn shared_allocations(&self) -> impl Iterator<Item = crate::shared::SharedAllocation> {
+                self.values().filter_map(|v| HeapSize::shared_allocation(v))
+            }
+        }
+
+        impl_from!(@from $name<$($gen),*>, $($bounds),*);
+    };
+    (@from $name:ident<$($gen:ident),*>, $($bounds:ident),*) => {
         impl<$($gen),*> From<$name<$($gen),*>> for Tracked<$name<$($gen),*>>
         where $($bounds: HeapSize),*
         {
             fn from(value: $name<$($gen),*>) -> Self {
-                let indirect_heap_memory = value.iter().map($fn).sum();
+                let mut tracked = Self::with_tally(value, 0);
+                tracked.recount();
+                tracked
+            }
+        }
+
+        impl<$($gen),*> Tracked<$name<$($gen),*>>
+        where
+            $name<$($gen),*>: Clone,
+            $($bounds: crate::CloneHeapExact),*
+        {
+            /// Like `Clone`, but copies the tally instead of walking the
+            /// clone, see [`CloneHeapExact`](crate::CloneHeapExact). The size
+            /// histogram and shrink policy carry over, peak tracking does not.
+            ///
+            /// # Panics
+            ///
+            /// In debug builds, if a clone allocates differently.
+            #[must_use]
+            pub fn clone_exact(&self) -> Self {
+                let inner = self.inner.clone();
+                // An estimated tally cannot be verified, but carries over.
+                let estimate = self.tally.estimate();
+                debug_assert!(
+                    estimate.is_some()
+                        || crate::IndirectHeapSize::indirect_heap_size(&inner) == self.tally.indirect(),
+                    "an element implementing CloneHeapExact allocates differently when cloned"
+                );
+                let mut tally = self.tally.clone_counters();
+                tally.set_estimate(estimate);
                 Self {
-                    inner: value,
-                    indirect_heap_memory,
+                    tally,
+                    ..Self::with_tally(inner, 0)
                 }
             }
         }
@@ -61,6 +149,47 @@ macro_rules! impl_from {
 }
 pub(crate) use impl_from;
 
+/// `Extend` and `FromIterator` for collections that keep every element, so
+/// the heap sizes of new elements can be summed up on the way in. Sets and
+/// maps implement them by hand, as they may drop or replace elements.
+macro_rules! impl_extend {
+    ($name:ident<$($gen:ident),*>, $elem:ident $(, $($where_clause:tt)*)?) => {
+        impl<$($gen),*> Extend<$elem> for Tracked<$name<$($gen),*>>
+        where
+            $elem: HeapSize,
+            $($($where_clause)*)?
+        {
+            fn extend<I: IntoIterator<Item = $elem>>(&mut self, iter: I) {
+                self.inner.extend(
+                    iter.into_iter()
+                        .inspect(|v| self.tally.add(crate::tally::ElementSize::of(v))),
+                );
+                self.after_op("extend");
+            }
+        }
+
+        impl<$($gen),*> FromIterator<$elem> for Tracked<$name<$($gen),*>>
+        where
+            $elem: HeapSize,
+            $name<$($gen),*>: FromIterator<$elem>,
+            $($($where_clause)*)?
+        {
+            fn from_iter<I: IntoIterator<Item = $elem>>(iter: I) -> Self {
+                let mut tally = crate::tally::Tally::new(0, 0);
+                let inner = iter
+                    .into_iter()
+                    .inspect(|v| tally.add(crate::tally::ElementSize::of(v)))
+                    .collect();
+                Self {
+                    tally,
+                    ..Self::with_tally(inner, 0)
+                }
+            }
+        }
+    };
+}
+pub(crate) use impl_extend;
+
 macro_rules! impl_shallow_heap_size {
     ($name:ident<$($gen:ident),*>, $size:expr) => {
         impl<$($gen),*> crate::ShallowHeapSize for $name<$($gen),*> {
@@ -72,3 +201,234 @@ macro_rules! impl_shallow_heap_size {
     };
 }
 pub(crate) use impl_shallow_heap_size;
+
+macro_rules! impl_shrink {
+    ($name:ident<$($gen:ident),*> $(, $($where_clause:tt)*)?) => {
+        impl<$($gen),*> crate::shrink::Shrink for $name<$($gen),*>
+        $(where $($where_clause)*)?
+        {
+            fn shrink_to_fit(&mut self) {
+                self.shrink_to_fit();
+            }
+        }
+    };
+}
+pub(crate) use impl_shrink;
+
+macro_rules! impl_capacity {
+    ($name:ident<$($gen:ident),*> $(, $($where_clause:tt)*)?) => {
+        impl<$($gen),*> Tracked<$name<$($gen),*>>
+        $(where $($where_clause)*)?
+        {
+            /// Reserves capacity for at least `additional` more elements.
+            pub fn reserve(&mut self, additional: usize) {
+                self.inner.reserve(additional);
+                self.after_op("reserve");
+            }
+
+            /// Tries to reserve capacity for at least `additional` more
+            /// elements.
+            ///
+            /// # Errors
+            ///
+            /// If the capacity overflows, or the allocator reports a failure.
+            pub fn try_reserve(
+                &mut self,
+                additional: usize,
+            ) -> Result<(), std::collections::TryReserveError> {
+                let result = self.inner.try_reserve(additional);
+                self.after_op("try_reserve");
+                result
+            }
+
+            /// Shrinks the capacity as much as possible.
+            pub fn shrink_to_fit(&mut self) {
+                self.inner.shrink_to_fit();
+                self.after_op("shrink_to_fit");
+            }
+
+            /// Shrinks the capacity with a lower bound of `min_capacity`.
+            pub fn shrink_to(&mut self, min_capacity: usize) {
+                self.inner.shrink_to(min_capacity);
+                self.after_op("shrink_to");
+            }
+        }
+    };
+}
+pub(crate) use impl_capacity;
+
+macro_rules! impl_reserve_exact {
+    ($name:ident<$($gen:ident),*> $(, $($where_clause:tt)*)?) => {
+        impl<$($gen),*> Tracked<$name<$($gen),*>>
+        $(where $($where_clause)*)?
+        {
+            /// Reserves the minimum capacity for at least `additional` more
+            /// elements.
+            pub fn reserve_exact(&mut self, additional: usize) {
+                self.inner.reserve_exact(additional);
+                self.after_op("reserve_exact");
+            }
+
+            /// Tries to reserve the minimum capacity for at least
+            /// `additional` more elements.
+            ///
+            /// # Errors
+            ///
+            /// If the capacity overflows, or the allocator reports a failure.
+            pub fn try_reserve_exact(
+                &mut self,
+                additional: usize,
+            ) -> Result<(), std::collections::TryReserveError> {
+                let result = self.inner.try_reserve_exact(additional);
+                self.after_op("try_reserve_exact");
+                result
+            }
+        }
+    };
+}
+pub(crate) use impl_reserve_exact;
+
+macro_rules! impl_permutations {
+    ($name:ident<$gen:ident>, $as_slice:expr) => {
+        impl<$gen> Tracked<$name<$gen>> {
+            fn as_mut_slice_untracked(&mut self) -> &mut [$gen] {
+                $as_slice(&mut self.inner)
+            }
+
+            /// Sorts the elements. Sorting never changes the tally.
+            pub fn sort(&mut self)
+            where
+                $gen: Ord,
+            {
+                self.as_mut_slice_untracked().sort();
+            }
+
+            pub fn sort_by<F>(&mut self, compare: F)
+            where
+                F: FnMut(&$gen, &$gen) -> std::cmp::Ordering,
+            {
+                self.as_mut_slice_untracked().sort_by(compare);
+            }
+
+            pub fn sort_by_key<K, F>(&mut self, f: F)
+            where
+                F: FnMut(&$gen) -> K,
+                K: Ord,
+            {
+                self.as_mut_slice_untracked().sort_by_key(f);
+            }
+
+            pub fn sort_by_cached_key<K, F>(&mut self, f: F)
+            where
+                F: FnMut(&$gen) -> K,
+                K: Ord,
+            {
+                self.as_mut_slice_untracked().sort_by_cached_key(f);
+            }
+
+            pub fn sort_unstable(&mut self)
+            where
+                $gen: Ord,
+            {
+                self.as_mut_slice_untracked().sort_unstable();
+            }
+
+            pub fn sort_unstable_by<F>(&mut self, compare: F)
+            where
+                F: FnMut(&$gen, &$gen) -> std::cmp::Ordering,
+            {
+                self.as_mut_slice_untracked().sort_unstable_by(compare);
+            }
+
+            pub fn sort_unstable_by_key<K, F>(&mut self, f: F)
+            where
+                F: FnMut(&$gen) -> K,
+                K: Ord,
+            {
+                self.as_mut_slice_untracked().sort_unstable_by_key(f);
+            }
+
+            pub fn reverse(&mut self) {
+                self.as_mut_slice_untracked().reverse();
+            }
+
+            /// Reorders the elements such that the element at `index` is at
+            /// its final sorted position. Unlike
+            /// [`slice::select_nth_unstable`], the parts are only handed out
+            /// immutably, as changes to them would not be tracked.
+            pub fn select_nth_unstable(&mut self, index: usize) -> (&[$gen], &$gen, &[$gen])
+            where
+                $gen: Ord,
+            {
+                let (left, nth, right) = self.as_mut_slice_untracked().select_nth_unstable(index);
+                (left, nth, right)
+            }
+
+            pub fn select_nth_unstable_by<F>(
+                &mut self,
+                index: usize,
+                compare: F,
+            ) -> (&[$gen], &$gen, &[$gen])
+            where
+                F: FnMut(&$gen, &$gen) -> std::cmp::Ordering,
+            {
+                let (left, nth, right) = self
+                    .as_mut_slice_untracked()
+                    .select_nth_unstable_by(index, compare);
+                (left, nth, right)
+            }
+
+            pub fn select_nth_unstable_by_key<K, F>(
+                &mut self,
+                index: usize,
+                f: F,
+            ) -> (&[$gen], &$gen, &[$gen])
+            where
+                F: FnMut(&$gen) -> K,
+                K: Ord,
+            {
+                let (left, nth, right) = self
+                    .as_mut_slice_untracked()
+                    .select_nth_unstable_by_key(index, f);
+                (left, nth, right)
+            }
+        }
+
+        impl<$gen> Tracked<$name<$gen>>
+        where
+            $gen: HeapSize,
+        {
+            /// Inserts `value` into an already sorted collection, after all
+            /// elements that are equal to it. Returns the index of the
+            /// inserted element.
+            pub fn insert_sorted(&mut self, value: $gen) -> usize
+            where
+                $gen: Ord,
+            {
+                self.insert_sorted_by(value, Ord::cmp)
+            }
+
+            /// Like [`Self::insert_sorted`], with a custom ordering.
+            pub fn insert_sorted_by<F>(&mut self, value: $gen, mut compare: F) -> usize
+            where
+                F: FnMut(&$gen, &$gen) -> std::cmp::Ordering,
+            {
+                let index = self
+                    .inner
+                    .partition_point(|x| compare(x, &value) != std::cmp::Ordering::Greater);
+                self.insert(index, value);
+                index
+            }
+
+            /// Like [`Self::insert_sorted`], ordered by the key `f` extracts.
+            pub fn insert_sorted_by_key<K, F>(&mut self, value: $gen, mut f: F) -> usize
+            where
+                F: FnMut(&$gen) -> K,
+                K: Ord,
+            {
+                self.insert_sorted_by(value, |a, b| f(a).cmp(&f(b)))
+            }
+        }
+    };
+}
+pub(crate) use impl_permutations;
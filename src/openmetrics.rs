@@ -0,0 +1,183 @@
+//! Rendering of [`TrackedCollection`]s in the [OpenMetrics] text format, as
+//! scraped by Prometheus.
+//!
+//! Add every collection to an [`Encoder`] under a name, then serve
+//! [`Encoder::finish`] from the `/metrics` handler.
+//!
+//! [OpenMetrics]: https://github.com/prometheus/OpenMetrics/blob/main/specification/OpenMetrics.md
+
+use std::fmt::{self, Write};
+
+use crate::TrackedCollection;
+
+/// Collects the sizes of named collections and renders them as gauges.
+///
+/// Every collection is labelled with `collection="<name>"` and any extra
+/// labels. Characters that are not allowed in the prefix or a label name are
+/// replaced by `_`.
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    prefix: String,
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, Debug)]
+struct Sample {
+    labels: String,
+    shallow: usize,
+    indirect: usize,
+    len: usize,
+    capacity: Option<usize>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    /// An encoder for metrics named `memtally_*`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_prefix("memtally")
+    }
+
+    /// An encoder for metrics named `<prefix>_*`.
+    #[must_use]
+    pub fn with_prefix(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: sanitize(prefix.into(), true),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Records the current sizes of `collection` under `name`.
+    pub fn add(&mut self, name: &str, collection: &dyn TrackedCollection) {
+        self.add_with_labels(name, &[], collection);
+    }
+
+    /// Like [`Encoder::add`], with additional labels.
+    pub fn add_with_labels(
+        &mut self,
+        name: &str,
+        labels: &[(&str, &str)],
+        collection: &dyn TrackedCollection,
+    ) {
+        let mut rendered = String::new();
+        write_label(&mut rendered, "collection", name);
+        for (key, value) in labels {
+            rendered.push(',');
+            write_label(&mut rendered, key, value);
+        }
+        self.samples.push(Sample {
+            labels: rendered,
+            shallow: collection.shallow_size(),
+            indirect: collection.indirect_size(),
+            len: collection.len(),
+            capacity: collection.tracked_capacity(),
+        });
+    }
+
+    /// Writes the metric families, without the final `# EOF`, e.g. to combine
+    /// them with other metrics.
+    ///
+    /// # Errors
+    ///
+    /// If writing to `out` fails.
+    pub fn encode(&self, out: &mut impl Write) -> fmt::Result {
+        self.family(
+            out,
+            "shallow",
+            Some("bytes"),
+            "Heap memory allocated by the collection itself.",
+            |s| Some(s.shallow),
+        )?;
+        self.family(
+            out,
+            "indirect",
+            Some("bytes"),
+            "Heap memory allocated by the elements of the collection.",
+            |s| Some(s.indirect),
+        )?;
+        self.family(
+            out,
+            "len",
+            None,
+            "Number of elements in the collection.",
+            |s| Some(s.len),
+        )?;
+        self.family(
+            out,
+            "capacity",
+            None,
+            "Number of elements the collection can hold without reallocating.",
+            |s| s.capacity,
+        )
+    }
+
+    /// Renders a complete exposition, ending in `# EOF`.
+    #[must_use]
+    pub fn finish(self) -> String {
+        let mut out = String::new();
+        self.encode(&mut out)
+            .expect("writing to a String does not fail");
+        out.push_str("# EOF\n");
+        out
+    }
+
+    fn family(
+        &self,
+        out: &mut impl Write,
+        metric: &str,
+        unit: Option<&str>,
+        help: &str,
+        value: impl Fn(&Sample) -> Option<usize>,
+    ) -> fmt::Result {
+        let name = match unit {
+            Some(unit) => format!("{}_{metric}_{unit}", self.prefix),
+            None => format!("{}_{metric}", self.prefix),
+        };
+        writeln!(out, "# TYPE {name} gauge")?;
+        if let Some(unit) = unit {
+            writeln!(out, "# UNIT {name} {unit}")?;
+        }
+        writeln!(out, "# HELP {name} {help}")?;
+        for sample in &self.samples {
+            if let Some(value) = value(sample) {
+                writeln!(out, "{name}{{{}}} {value}", sample.labels)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Makes `name` match `[a-zA-Z_:][a-zA-Z0-9_:]*`, or the same without `:`
+/// for label names.
+fn sanitize(name: String, allow_colon: bool) -> String {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || (allow_colon && c == ':');
+    let starts_well = name.starts_with(|c: char| !c.is_ascii_digit());
+    if starts_well && name.chars().all(valid) {
+        return name;
+    }
+    let mut sanitized = String::with_capacity(name.len() + 1);
+    if !starts_well {
+        sanitized.push('_');
+    }
+    sanitized.extend(name.chars().map(|c| if valid(c) { c } else { '_' }));
+    sanitized
+}
+
+fn write_label(out: &mut String, key: &str, value: &str) {
+    out.push_str(&sanitize(key.to_owned(), false));
+    out.push_str("=\"");
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,347 @@
+//! Parallel recounts and extension with [`rayon`].
+//!
+//! Without specialization, `From`, `Clone` and
+//! [`TrackedCollection::recalculate`](crate::TrackedCollection::recalculate)
+//! cannot switch to a parallel walk on their own when the elements are
+//! `Sync`. Call [`Tracked::par_from`], [`Tracked::par_clone`] and
+//! [`Tracked::par_recalculate`] instead.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+    hash::{BuildHasher, Hash},
+    sync::{Mutex, PoisonError},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    HeapSize, IndirectHeapSize, ShallowHeapSize, SizeHistogram, Tracked, shared::SharedAllocation,
+    tally::ElementSize,
+};
+
+/// Like [`IndirectHeapSize`], but walks the elements in parallel.
+pub trait ParIndirectHeapSize: IndirectHeapSize {
+    /// See [`IndirectHeapSize::element_heap_sizes`]. Every size comes with
+    /// the allocation that the element shares, see
+    /// [`HeapSize::shared_allocation`].
+    fn par_element_heap_sizes(
+        &self,
+    ) -> impl ParallelIterator<Item = (usize, Option<SharedAllocation>)>;
+
+    /// See [`IndirectHeapSize::indirect_key_heap_size`].
+    #[must_use]
+    fn par_indirect_key_heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: HeapSize + Sync> ParIndirectHeapSize for Vec<T> {
+    fn par_element_heap_sizes(
+        &self,
+    ) -> impl ParallelIterator<Item = (usize, Option<SharedAllocation>)> {
+        self.par_iter()
+            .map(|v| (T::heap_size(v), T::shared_allocation(v)))
+    }
+}
+
+impl<T: HeapSize + Sync> ParIndirectHeapSize for VecDeque<T> {
+    fn par_element_heap_sizes(
+        &self,
+    ) -> impl ParallelIterator<Item = (usize, Option<SharedAllocation>)> {
+        self.par_iter()
+            .map(|v| (T::heap_size(v), T::shared_allocation(v)))
+    }
+}
+
+impl<T: HeapSize + Ord + Sync> ParIndirectHeapSize for BTreeSet<T> {
+    fn par_element_heap_sizes(
+        &self,
+    ) -> impl ParallelIterator<Item = (usize, Option<SharedAllocation>)> {
+        self.par_iter()
+            .map(|v| (T::heap_size(v), T::shared_allocation(v)))
+    }
+}
+
+impl<T, S> ParIndirectHeapSize for HashSet<T, S>
+where
+    T: HeapSize + Eq + Hash + Sync,
+    S: BuildHasher + Sync,
+{
+    fn par_element_heap_sizes(
+        &self,
+    ) -> impl ParallelIterator<Item = (usize, Option<SharedAllocation>)> {
+        self.par_iter()
+            .map(|v| (T::heap_size(v), T::shared_allocation(v)))
+    }
+}
+
+impl<K, V> ParIndirectHeapSize for BTreeMap<K, V>
+where
+    K: HeapSize + Ord + Sync,
+    V: HeapSize + Sync,
+{
+    fn par_element_heap_sizes(
+        &self,
+    ) -> impl ParallelIterator<Item = (usize, Option<SharedAllocation>)> {
+        self.par_iter()
+            .map(|(_, v)| (V::heap_size(v), V::shared_allocation(v)))
+    }
+
+    fn par_indirect_key_heap_size(&self) -> usize {
+        self.par_iter().map(|(k, _)| K::heap_size(k)).sum()
+    }
+}
+
+impl<K, V, S> ParIndirectHeapSize for HashMap<K, V, S>
+where
+    K: HeapSize + Eq + Hash + Sync,
+    V: HeapSize + Sync,
+    S: BuildHasher + Sync,
+{
+    fn par_element_heap_sizes(
+        &self,
+    ) -> impl ParallelIterator<Item = (usize, Option<SharedAllocation>)> {
+        self.par_iter()
+            .map(|(_, v)| (V::heap_size(v), V::shared_allocation(v)))
+    }
+
+    fn par_indirect_key_heap_size(&self) -> usize {
+        self.par_iter().map(|(k, _)| K::heap_size(k)).sum()
+    }
+}
+
+/// The summed sizes, and their histogram if wanted, of one part of a
+/// parallel walk, with the allocations that the elements share.
+struct Count {
+    sum: usize,
+    histogram: Option<SizeHistogram>,
+    shared: Vec<SharedAllocation>,
+}
+
+impl Count {
+    fn new(histogram: bool) -> Self {
+        Self {
+            sum: 0,
+            histogram: histogram.then(SizeHistogram::new),
+            shared: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, size: usize) {
+        self.sum += size;
+        if let Some(histogram) = &mut self.histogram {
+            histogram.insert_many(size, 1);
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.sum += other.sum;
+        if let (Some(histogram), Some(other)) = (&mut self.histogram, &other.histogram) {
+            histogram.merge(other);
+        }
+        self.shared.extend_from_slice(&other.shared);
+    }
+}
+
+impl<C: ParIndirectHeapSize + ShallowHeapSize> Tracked<C> {
+    /// Like `From`, but walks the elements in parallel.
+    pub fn par_from(inner: C) -> Self {
+        let mut tracked = Self::with_tally(inner, 0);
+        tracked.par_recount();
+        tracked
+    }
+
+    /// Like `Clone`, but recounts the clone in parallel.
+    #[must_use]
+    pub fn par_clone(&self) -> Self
+    where
+        C: Clone,
+    {
+        let mut clone = Self::with_tally(self.inner.clone(), 0);
+        clone.tally.set_shrink_policy(self.tally.shrink_policy());
+        clone.par_recount();
+        clone
+    }
+
+    /// Like [`TrackedCollection::recalculate`](crate::TrackedCollection::recalculate),
+    /// but walks the elements in parallel.
+    pub fn par_recalculate(&mut self) {
+        self.par_recount();
+        self.after_op("recalculate");
+    }
+
+    fn par_recount(&mut self) {
+        let histogram = self.tally.histogram().is_some();
+        let values = self
+            .inner
+            .par_element_heap_sizes()
+            .fold(
+                || Count::new(histogram),
+                |mut count, (size, shared)| {
+                    count.insert(size);
+                    count.shared.extend(shared);
+                    count
+                },
+            )
+            .reduce(
+                || Count::new(histogram),
+                |mut count, other| {
+                    count.merge(&other);
+                    count
+                },
+            );
+        let keys = self.inner.par_indirect_key_heap_size();
+        self.tally.reset_counted(
+            values.sum,
+            keys,
+            values.histogram,
+            values.shared.into_iter(),
+        );
+    }
+}
+
+/// Sums the sizes of the elements that one rayon job passes on, and adds
+/// them to the shared total when the job is done. This keeps the shared
+/// total out of the hot loop.
+struct PartialCount<'a> {
+    total: &'a Mutex<Count>,
+    count: Count,
+}
+
+impl Drop for PartialCount<'_> {
+    fn drop(&mut self) {
+        let mut total = self.total.lock().unwrap_or_else(PoisonError::into_inner);
+        total.merge(&self.count);
+    }
+}
+
+impl<C: ShallowHeapSize> Tracked<C> {
+    /// Extends a collection that keeps every element, summing up the heap
+    /// sizes of the new elements in parallel.
+    fn par_extend_counted<I, T>(&mut self, iter: I)
+    where
+        C: ParallelExtend<T>,
+        I: IntoParallelIterator<Item = T>,
+        T: HeapSize + Send,
+    {
+        let histogram = self.tally.histogram().is_some();
+        let total = Mutex::new(Count::new(histogram));
+        self.inner.par_extend(iter.into_par_iter().map_init(
+            || PartialCount {
+                total: &total,
+                count: Count::new(histogram),
+            },
+            |partial, v| {
+                partial.count.insert(T::heap_size(&v));
+                partial.count.shared.extend(v.shared_allocation());
+                v
+            },
+        ));
+        let count = total.into_inner().unwrap_or_else(PoisonError::into_inner);
+        self.tally
+            .add_counted(count.sum, count.histogram.as_ref(), &count.shared);
+        self.after_op("par_extend");
+    }
+}
+
+macro_rules! impl_par_extend {
+    ($name:ident<$gen:ident> $(, $($where_clause:tt)*)?) => {
+        impl<$gen: HeapSize + Send> Tracked<$name<$gen>>
+        $(where $($where_clause)*)?
+        {
+            /// Like `Extend`, but takes a parallel iterator and sums up the
+            /// heap sizes of the new elements in parallel.
+            pub fn par_extend<I: IntoParallelIterator<Item = $gen>>(&mut self, iter: I) {
+                self.par_extend_counted(iter);
+            }
+        }
+    };
+}
+
+impl_par_extend!(Vec<T>);
+impl_par_extend!(VecDeque<T>);
+impl_par_extend!(BinaryHeap<T>, T: Ord);
+
+/// Measures the elements of `iter` in parallel with `size`.
+fn par_measure<I, T>(iter: I, size: impl Fn(&T) -> ElementSize + Sync) -> Vec<(T, ElementSize)>
+where
+    I: IntoParallelIterator<Item = T>,
+    T: Send,
+{
+    iter.into_par_iter()
+        .map(|v| {
+            let v_size = size(&v);
+            (v, v_size)
+        })
+        .collect()
+}
+
+impl<T: HeapSize + Send> Tracked<BTreeSet<T>>
+where
+    T: Ord,
+{
+    /// Like `Extend`, but takes a parallel iterator and measures the new
+    /// values in parallel. They are inserted one by one afterwards, so that
+    /// duplicates are dropped like in `Extend`.
+    pub fn par_extend<I: IntoParallelIterator<Item = T>>(&mut self, iter: I) {
+        for (key, key_size) in par_measure(iter, ElementSize::of) {
+            if self.inner.insert(key) {
+                self.tally.add(key_size);
+            }
+        }
+        self.after_op("par_extend");
+    }
+}
+
+impl<T: HeapSize + Send, S> Tracked<HashSet<T, S>>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Like `Extend`, but takes a parallel iterator and measures the new
+    /// values in parallel. They are inserted one by one afterwards, so that
+    /// duplicates are dropped like in `Extend`.
+    pub fn par_extend<I: IntoParallelIterator<Item = T>>(&mut self, iter: I) {
+        for (key, key_size) in par_measure(iter, ElementSize::of) {
+            if self.inner.insert(key) {
+                self.tally.add(key_size);
+            }
+        }
+        self.after_op("par_extend");
+    }
+}
+
+impl<K: HeapSize + Send, V: HeapSize + Send> Tracked<BTreeMap<K, V>>
+where
+    K: Ord,
+{
+    /// Like `Extend`, but takes a parallel iterator and measures the new
+    /// values in parallel. The entries are inserted one by one afterwards,
+    /// so that existing values are replaced like in `Extend`.
+    pub fn par_extend<I: IntoParallelIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let entries = par_measure(iter, |(_, v)| ElementSize::of(v));
+        self.sync_largest();
+        for ((key, value), value_size) in entries {
+            self.insert_sized(key, value, value_size);
+        }
+        self.after_op("par_extend");
+    }
+}
+
+impl<K: HeapSize + Send, V: HeapSize + Send, S> Tracked<HashMap<K, V, S>>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Like `Extend`, but takes a parallel iterator and measures the new
+    /// values in parallel. The entries are inserted one by one afterwards,
+    /// so that existing values are replaced like in `Extend`.
+    pub fn par_extend<I: IntoParallelIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let entries = par_measure(iter, |(_, v)| ElementSize::of(v));
+        self.sync_largest();
+        for ((key, value), value_size) in entries {
+            self.insert_sized(key, value, value_size);
+        }
+        self.after_op("par_extend");
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{ShallowHeapSize, Tracked};
+
+/// The highest heap usage a [`Tracked`] collection has reached since peak
+/// tracking was enabled or last reset.
+///
+/// Usage is sampled at the end of every operation, so a temporary spike
+/// within a single operation is not recorded. The peaks of the shallow and
+/// indirect heap usage are tracked independently and need not have been
+/// reached at the same time as the peak of the total.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeakUsage {
+    /// Highest heap usage of the collection itself.
+    pub shallow: usize,
+    /// Highest heap usage of the elements.
+    pub indirect: usize,
+    /// Highest total heap usage.
+    pub total: usize,
+    /// Number of operations since tracking started, up to and including the
+    /// one that reached `total`.
+    pub total_at_op: u64,
+    /// Name of the operation that reached `total`, e.g. `"push"`.
+    pub total_op: &'static str,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Peak {
+    usage: PeakUsage,
+    /// Shallow heap usage as of the last operation.
+    shallow: usize,
+    ops: u64,
+}
+
+impl Peak {
+    fn new(shallow: usize, indirect: usize) -> Self {
+        Self {
+            usage: PeakUsage {
+                shallow,
+                indirect,
+                total: shallow + indirect,
+                total_at_op: 0,
+                total_op: "start",
+            },
+            shallow,
+            ops: 0,
+        }
+    }
+
+    pub(crate) fn shallow(&self) -> usize {
+        self.shallow
+    }
+
+    pub(crate) fn observe(&mut self, op: &'static str, shallow: usize, indirect: usize) {
+        self.ops += 1;
+        self.shallow = shallow;
+        let usage = &mut self.usage;
+        usage.shallow = usage.shallow.max(shallow);
+        usage.indirect = usage.indirect.max(indirect);
+        if shallow + indirect > usage.total {
+            usage.total = shallow + indirect;
+            usage.total_at_op = self.ops;
+            usage.total_op = op;
+        }
+    }
+}
+
+impl<C: ShallowHeapSize> Tracked<C> {
+    /// Start recording the peak heap usage, beginning with the current usage.
+    /// Does nothing if peak tracking is already enabled.
+    ///
+    /// This adds a few comparisons to every operation.
+    pub fn enable_peak_tracking(&mut self) {
+        if self.tally.peak().is_none() {
+            self.reset_peak();
+        }
+    }
+
+    pub fn disable_peak_tracking(&mut self) {
+        self.tally.set_peak(None);
+    }
+
+    /// Restart recording the peak heap usage from the current usage. Enables
+    /// peak tracking if it was disabled.
+    pub fn reset_peak(&mut self) {
+        let peak = Peak::new(self.inner.shallow_heap_size(), self.tally.indirect());
+        self.tally.set_peak(Some(peak));
+    }
+
+    /// The peak heap usage, or `None` if peak tracking is disabled.
+    #[must_use]
+    pub fn peak(&self) -> Option<PeakUsage> {
+        self.tally.peak().map(|peak| peak.usage)
+    }
+}
@@ -0,0 +1,112 @@
+//! A process-wide list of named [`Tracked`] collections, e.g. for a debug
+//! endpoint that shows where memory goes.
+//!
+//! A collection joins the registry with [`Tracked::register`] and leaves it
+//! when it is dropped. [`snapshot`] returns the sizes of all registered
+//! collections, as of their last operation.
+
+use std::sync::{
+    Arc, Mutex, PoisonError, Weak,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{ShallowHeapSize, Tracked};
+
+static REGISTRY: Mutex<Vec<(String, Weak<Sizes>)>> = Mutex::new(Vec::new());
+
+/// The sizes of a collection in the registry, as of its last operation.
+#[derive(Debug, Default)]
+pub(crate) struct Sizes {
+    shallow: AtomicUsize,
+    indirect: AtomicUsize,
+}
+
+impl Sizes {
+    pub(crate) fn set_shallow(&self, shallow: usize) {
+        self.shallow.store(shallow, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_indirect(&self, indirect: usize) {
+        self.indirect.store(indirect, Ordering::Relaxed);
+    }
+
+    fn report(&self) -> Report {
+        Report {
+            shallow: self.shallow.load(Ordering::Relaxed),
+            indirect: self.indirect.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The heap usage of a collection, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Report {
+    /// What the collection allocates itself.
+    pub shallow: usize,
+    /// What the elements allocate.
+    pub indirect: usize,
+}
+
+impl Report {
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.shallow + self.indirect
+    }
+}
+
+/// A registered collection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entry {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub report: Report,
+}
+
+/// The sizes of all registered collections that are still alive, in the
+/// order they were registered. Names need not be unique.
+///
+/// With the `serde` feature, the result serializes to e.g.
+/// `[{"name":"sessions","shallow":1024,"indirect":4096}]` in JSON.
+#[must_use]
+pub fn snapshot() -> Vec<Entry> {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut entries = Vec::with_capacity(registry.len());
+    registry.retain(|(name, sizes)| {
+        let Some(sizes) = sizes.upgrade() else {
+            return false;
+        };
+        entries.push(Entry {
+            name: name.clone(),
+            report: sizes.report(),
+        });
+        true
+    });
+    entries
+}
+
+impl<C: ShallowHeapSize> Tracked<C> {
+    /// Adds the collection to the [registry](crate::registry) under `name`,
+    /// until it is dropped or unregistered. Registering again renames it.
+    ///
+    /// Every operation then publishes the sizes with a few atomic stores.
+    pub fn register(&mut self, name: impl Into<String>) {
+        self.unregister();
+        let sizes = Arc::new(Sizes::default());
+        sizes.set_shallow(self.inner.shallow_heap_size());
+        sizes.set_indirect(self.tally.indirect());
+
+        let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        registry.retain(|(_, sizes)| sizes.strong_count() > 0);
+        registry.push((name.into(), Arc::downgrade(&sizes)));
+        self.tally.set_registration(Some(sizes));
+    }
+}
+
+impl<C> Tracked<C> {
+    pub fn unregister(&mut self) {
+        // The registry drops the entry once it notices.
+        self.tally.set_registration(None);
+    }
+}
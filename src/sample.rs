@@ -0,0 +1,133 @@
+use std::{
+    collections::{HashSet, hash_map::RandomState},
+    hash::BuildHasher,
+};
+
+use crate::{IndirectHeapSize, ShallowHeapSize, Tracked, TrackedCollection, tally::Tally};
+
+/// The uncertainty of a tally that was estimated from a sample.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Estimate {
+    /// Half the width of the 95% confidence interval, in bytes.
+    pub(crate) margin: usize,
+}
+
+/// A small pseudo-random generator, good enough to pick sample indices.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new() -> Self {
+        Self(RandomState::new().hash_one(0u8))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, up to a negligible bias.
+    #[allow(clippy::cast_possible_truncation, reason = "The result is less than n")]
+    fn below(&mut self, n: usize) -> usize {
+        ((u128::from(self.next()) * n as u128) >> 64) as usize
+    }
+}
+
+/// `k` distinct indices in `0..n`, chosen uniformly at random, in ascending
+/// order (Floyd's algorithm).
+fn sample_indices(n: usize, k: usize) -> Vec<usize> {
+    let mut rng = SplitMix64::new();
+    let mut chosen = HashSet::with_capacity(k);
+    for j in n - k..n {
+        let i = rng.below(j + 1);
+        if !chosen.insert(i) {
+            chosen.insert(j);
+        }
+    }
+    let mut indices: Vec<usize> = chosen.into_iter().collect();
+    indices.sort_unstable();
+    indices
+}
+
+/// The items of `iter` at the ascending `indices`. Skips with
+/// [`Iterator::nth`], which is cheap for slice-backed collections.
+pub(crate) fn pick<I: Iterator>(mut iter: I, indices: &[usize]) -> impl Iterator<Item = I::Item> {
+    let mut next = 0;
+    indices.iter().map_while(move |&i| {
+        let item = iter.nth(i - next);
+        next = i + 1;
+        item
+    })
+}
+
+impl<C> Tracked<C>
+where
+    C: IndirectHeapSize + ShallowHeapSize,
+    Self: TrackedCollection,
+{
+    /// Like `From`, but estimates the heap usage of the elements from
+    /// `sample_size` of them, chosen at random, instead of walking all of
+    /// them. [`Tracked::confidence_interval`] tells how accurate the estimate
+    /// is.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        reason = "Estimates are approximate anyway"
+    )]
+    pub fn from_sampled(inner: C, sample_size: usize) -> Self {
+        let mut tracked = Self::with_tally(inner, 0);
+        let len = tracked.len();
+        if sample_size >= len {
+            tracked.recount();
+            return tracked;
+        }
+        let indices = sample_indices(len, sample_size.max(1));
+        let (mut values, mut keys, mut sum_sq) = (0usize, 0usize, 0.0);
+        for (value, key) in tracked.inner.sampled_heap_sizes(&indices) {
+            values += value;
+            keys += key;
+            sum_sq += ((value + key) as f64).powi(2);
+        }
+
+        let (n, k) = (len as f64, indices.len() as f64);
+        let scale = |sum: usize| (sum as f64 * n / k).round() as usize;
+        let mean = (values + keys) as f64 / k;
+        let variance = if k > 1.0 {
+            ((sum_sq - k * mean * mean) / (k - 1.0)).max(0.0)
+        } else {
+            // A single element tells nothing about the spread, so fall back
+            // to its size as the standard deviation.
+            mean * mean
+        };
+        // Standard error of the total, with the finite population correction.
+        let std_error = n * (variance / k * (n - k) / (n - 1.0)).sqrt();
+        let keys = scale(keys);
+        tracked.tally = Tally::new(scale(values) + keys, keys);
+        tracked.tally.set_estimate(Some(Estimate {
+            margin: (1.96 * std_error).ceil() as usize,
+        }));
+        tracked
+    }
+}
+
+impl<C> Tracked<C> {
+    /// The 95% confidence interval of the heap usage of the elements, if it
+    /// was estimated by [`Tracked::from_sampled`], or `None` if it is exact.
+    ///
+    /// Operations track exact changes, so the interval moves along with the
+    /// estimate but does not grow. A recount, e.g. through
+    /// [`TrackedCollection::recalculate`], makes the tally exact again.
+    #[must_use]
+    pub fn confidence_interval(&self) -> Option<(usize, usize)> {
+        self.tally.estimate().map(|estimate| {
+            let indirect = self.tally.indirect();
+            (
+                indirect.saturating_sub(estimate.margin),
+                indirect + estimate.margin,
+            )
+        })
+    }
+}
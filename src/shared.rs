@@ -0,0 +1,263 @@
+//! [`HeapSize`] policies for reference-counted pointers.
+//!
+//! Counting the pointee for every handle overcounts shared data, so `Rc` and
+//! `Arc` do not implement [`HeapSize`] themselves. Instead, wrap them in one
+//! of [`SharedFull`], [`SharedZero`], [`SharedSplit`] and [`SharedOnce`] to
+//! choose how they are counted.
+
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    hash::{Hash, Hasher},
+    mem,
+    ops::Deref,
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::HeapSize;
+
+/// A reference-counted pointer, i.e. `Rc<T>` or `Arc<T>`.
+pub trait SharedPointer: Deref {
+    /// The number of handles to the allocation, as `Rc::strong_count`.
+    fn strong_count(this: &Self) -> usize;
+
+    /// The address of the allocation, to tell allocations apart.
+    fn addr(this: &Self) -> usize;
+
+    /// The size of the allocation, i.e. of the pointee and the counters.
+    fn allocation_size(this: &Self) -> usize {
+        2 * size_of::<usize>() + mem::size_of_val(&**this)
+    }
+}
+
+impl<T: ?Sized> SharedPointer for Rc<T> {
+    fn strong_count(this: &Self) -> usize {
+        Rc::strong_count(this)
+    }
+
+    fn addr(this: &Self) -> usize {
+        Rc::as_ptr(this).cast::<()>() as usize
+    }
+}
+
+impl<T: ?Sized> SharedPointer for Arc<T> {
+    fn strong_count(this: &Self) -> usize {
+        Arc::strong_count(this)
+    }
+
+    fn addr(this: &Self) -> usize {
+        Arc::as_ptr(this).cast::<()>() as usize
+    }
+}
+
+/// An allocation that several elements of a collection share and that is
+/// counted once, see [`HeapSize::shared_allocation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharedAllocation {
+    /// Tells allocations apart.
+    pub addr: usize,
+    /// What the allocation uses in total.
+    pub size: usize,
+}
+
+/// The heap usage of `allocations`, counting every one once.
+pub(crate) fn once_size(allocations: impl Iterator<Item = SharedAllocation>) -> usize {
+    let mut seen = HashSet::new();
+    allocations
+        .filter(|allocation| seen.insert(allocation.addr))
+        .map(|allocation| allocation.size)
+        .sum()
+}
+
+/// What the allocation behind `pointer` uses in total, including what the
+/// pointee allocates itself.
+fn shared_size<P>(pointer: &P) -> usize
+where
+    P: SharedPointer,
+    P::Target: HeapSize,
+{
+    // Not `pointer.heap_size()`, which may resolve to the size of the
+    // reference itself with a compatibility layer.
+    P::allocation_size(pointer) + P::Target::heap_size(pointer)
+}
+
+macro_rules! shared_wrapper {
+    ($(#[$doc:meta])* $name:ident, |$pointer:ident| $size:expr) => {
+        shared_wrapper!($(#[$doc])* $name, |$pointer| $size, |_pointer| None);
+    };
+    ($(#[$doc:meta])* $name:ident, |$pointer:ident| $size:expr, |$shared_pointer:ident| $shared:expr) => {
+        $(#[$doc])*
+        #[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name<P>(pub P);
+
+        impl<P> $name<P> {
+            pub fn into_inner(self) -> P {
+                self.0
+            }
+        }
+
+        impl<P> Deref for $name<P> {
+            type Target = P;
+
+            fn deref(&self) -> &P {
+                &self.0
+            }
+        }
+
+        impl<P> From<P> for $name<P> {
+            fn from(pointer: P) -> Self {
+                Self(pointer)
+            }
+        }
+
+        impl<P> HeapSize for $name<P>
+        where
+            P: SharedPointer,
+            P::Target: HeapSize,
+        {
+            fn heap_size(&self) -> usize {
+                let $pointer = &self.0;
+                $size
+            }
+
+            fn shared_allocation(&self) -> Option<SharedAllocation> {
+                let $shared_pointer = &self.0;
+                $shared
+            }
+        }
+    };
+}
+
+shared_wrapper!(
+    /// Counts the whole allocation for every handle, as if it was not
+    /// shared. Fits pointers that are rarely shared.
+    SharedFull,
+    |pointer| shared_size(pointer)
+);
+
+shared_wrapper!(
+    /// Counts nothing, e.g. for handles to data that is accounted for
+    /// elsewhere.
+    SharedZero,
+    |_pointer| 0
+);
+
+/// Counts an equal share of the allocation for every handle, so that the
+/// handles that exist when one is wrapped count it about once.
+///
+/// The share is taken when the handle is wrapped or cloned, and kept for as
+/// long as the wrapper lives, so that the tally of a [`Tracked`](crate::Tracked) collection
+/// stays consistent while handles come and go elsewhere. Rewrap handles, e.g.
+/// through [`Tracked::with_inner_mut`](crate::Tracked::with_inner_mut), to take their share anew.
+#[derive(Debug)]
+pub struct SharedSplit<P> {
+    pointer: P,
+    share: usize,
+}
+
+impl<P> SharedSplit<P>
+where
+    P: SharedPointer,
+    P::Target: HeapSize,
+{
+    pub fn new(pointer: P) -> Self {
+        let share = shared_size(&pointer) / P::strong_count(&pointer);
+        Self { pointer, share }
+    }
+}
+
+impl<P> SharedSplit<P> {
+    pub fn into_inner(self) -> P {
+        self.pointer
+    }
+}
+
+impl<P> Deref for SharedSplit<P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.pointer
+    }
+}
+
+impl<P> From<P> for SharedSplit<P>
+where
+    P: SharedPointer,
+    P::Target: HeapSize,
+{
+    fn from(pointer: P) -> Self {
+        Self::new(pointer)
+    }
+}
+
+impl<P> Clone for SharedSplit<P>
+where
+    P: SharedPointer + Clone,
+    P::Target: HeapSize,
+{
+    fn clone(&self) -> Self {
+        Self::new(self.pointer.clone())
+    }
+}
+
+impl<P> Default for SharedSplit<P>
+where
+    P: SharedPointer + Default,
+    P::Target: HeapSize,
+{
+    fn default() -> Self {
+        Self::new(P::default())
+    }
+}
+
+// The share is bookkeeping, so only the pointers are compared.
+impl<P: PartialEq> PartialEq for SharedSplit<P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.pointer == other.pointer
+    }
+}
+
+impl<P: Eq> Eq for SharedSplit<P> {}
+
+impl<P: PartialOrd> PartialOrd for SharedSplit<P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.pointer.partial_cmp(&other.pointer)
+    }
+}
+
+impl<P: Ord> Ord for SharedSplit<P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.pointer.cmp(&other.pointer)
+    }
+}
+
+impl<P: Hash> Hash for SharedSplit<P> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pointer.hash(state);
+    }
+}
+
+impl<P> HeapSize for SharedSplit<P> {
+    fn heap_size(&self) -> usize {
+        self.share
+    }
+}
+
+shared_wrapper!(
+    /// Counts every allocation once per collection, no matter how many of its
+    /// handles the collection holds.
+    ///
+    /// The tally keeps the number of handles per allocation, so the first
+    /// handle that is added counts the allocation and the last one that is
+    /// removed takes it out again. This only works for the elements of a
+    /// collection and the values of a map, not for handles nested in them.
+    /// [`Tracked::from_sampled`](crate::Tracked::from_sampled) does not see the
+    /// allocations.
+    SharedOnce,
+    |_pointer| 0,
+    |pointer| Some(SharedAllocation {
+        addr: P::addr(pointer),
+        size: shared_size(pointer),
+    })
+);
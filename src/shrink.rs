@@ -0,0 +1,73 @@
+use crate::{Collection, Tracked};
+
+/// Automatically releases spare capacity of a [`Tracked`] collection once
+/// too much of its allocation is unused.
+///
+/// The check runs after every removal through the wrapper. If the fraction
+/// of unused slots exceeds `max_unused_fraction`, the collection is shrunk
+/// to fit. As collections usually double their capacity when they grow, a
+/// fraction of `0.5` or below makes a collection that hovers around a
+/// capacity boundary reallocate on nearly every insertion and removal.
+/// `clear` keeps the allocation, as it is usually followed by refilling.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShrinkPolicy {
+    max_unused_fraction: f64,
+}
+
+impl ShrinkPolicy {
+    /// # Panics
+    ///
+    /// If `max_unused_fraction` is not within `0.0..=1.0`.
+    #[must_use]
+    pub fn new(max_unused_fraction: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&max_unused_fraction),
+            "max_unused_fraction must be within 0.0..=1.0, got {max_unused_fraction}"
+        );
+        Self {
+            max_unused_fraction,
+        }
+    }
+
+    #[must_use]
+    pub fn max_unused_fraction(&self) -> f64 {
+        self.max_unused_fraction
+    }
+
+    #[allow(clippy::cast_precision_loss, reason = "An estimate is sufficient")]
+    fn should_shrink(self, len: usize, capacity: usize) -> bool {
+        let unused = capacity.saturating_sub(len);
+        unused > 0 && unused as f64 > self.max_unused_fraction * capacity as f64
+    }
+}
+
+/// Used for containers that can release their spare capacity.
+pub(crate) trait Shrink: Collection {
+    fn shrink_to_fit(&mut self);
+}
+
+impl<C> Tracked<C> {
+    /// Set the policy for automatically shrinking the collection, or `None`
+    /// to only shrink on explicit request.
+    pub fn set_shrink_policy(&mut self, policy: Option<ShrinkPolicy>) {
+        self.tally.set_shrink_policy(policy);
+    }
+
+    #[must_use]
+    pub fn shrink_policy(&self) -> Option<ShrinkPolicy> {
+        self.tally.shrink_policy()
+    }
+
+    /// Shrinks the collection if the configured [`ShrinkPolicy`] asks for it.
+    pub(crate) fn maybe_shrink(&mut self)
+    where
+        C: Shrink,
+    {
+        if let Some(policy) = self.tally.shrink_policy()
+            && let Some(capacity) = self.inner.capacity()
+            && policy.should_shrink(self.inner.len(), capacity)
+        {
+            self.inner.shrink_to_fit();
+        }
+    }
+}
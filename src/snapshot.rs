@@ -0,0 +1,248 @@
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    TrackedCollection,
+    registry::{self, Entry, Report},
+};
+
+/// The heap usage of a set of named collections at one point in time, to be
+/// compared with another one through [`MemorySnapshot::diff`].
+///
+/// With the `serde` feature, it serializes to e.g.
+/// `{"collections":[{"name":"sessions","shallow":1024,"indirect":4096}]}`,
+/// with sizes in bytes. This is also the format that
+/// [`MemorySnapshot::to_json`] writes and the `memtally-report` tool reads.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemorySnapshot {
+    /// Sorted by name, with unique names.
+    collections: Vec<Entry>,
+}
+
+impl MemorySnapshot {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A snapshot of all collections in the [registry](crate::registry).
+    #[must_use]
+    pub fn from_registry() -> Self {
+        registry::snapshot().into_iter().collect()
+    }
+
+    /// Records the current sizes of `collection` under `name`. Collections
+    /// added under the same name are summed up.
+    pub fn add(&mut self, name: &str, collection: &dyn TrackedCollection) {
+        self.insert(Entry {
+            name: name.to_owned(),
+            report: Report {
+                shallow: collection.shallow_size(),
+                indirect: collection.indirect_size(),
+            },
+        });
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        match self
+            .collections
+            .binary_search_by(|other| other.name.cmp(&entry.name))
+        {
+            Ok(i) => {
+                let report = &mut self.collections[i].report;
+                report.shallow += entry.report.shallow;
+                report.indirect += entry.report.indirect;
+            }
+            Err(i) => self.collections.insert(i, entry),
+        }
+    }
+
+    /// The collections, sorted by name.
+    #[must_use]
+    pub fn collections(&self) -> &[Entry] {
+        &self.collections
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Report> {
+        self.collections
+            .binary_search_by(|entry| entry.name.as_str().cmp(name))
+            .ok()
+            .map(|i| self.collections[i].report)
+    }
+
+    /// Total heap usage of all collections.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.collections
+            .iter()
+            .map(|entry| entry.report.total())
+            .sum()
+    }
+
+    /// The snapshot as JSON, see [`MemorySnapshot`] for the format.
+    #[cfg(feature = "json")]
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a snapshot serializes")
+    }
+
+    /// Reads a snapshot written by [`MemorySnapshot::to_json`].
+    ///
+    /// # Errors
+    ///
+    /// If `json` is not a valid snapshot.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        let snapshot: Self = serde_json::from_str(json)?;
+        // Restore the sorting by unique names, in case the file was edited.
+        Ok(snapshot.collections.into_iter().collect())
+    }
+
+    /// The changes from `self` to the later snapshot `other`, largest first.
+    /// Collections whose total did not change are left out.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> SnapshotDiff {
+        let names: BTreeSet<&str> = self
+            .collections
+            .iter()
+            .chain(&other.collections)
+            .map(|entry| entry.name.as_str())
+            .collect();
+        let mut changes: Vec<Change> = names
+            .into_iter()
+            .filter_map(|name| Change::new(name, self.get(name), other.get(name)))
+            .collect();
+        changes.sort_by(|a, b| {
+            b.delta
+                .unsigned_abs()
+                .cmp(&a.delta.unsigned_abs())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        SnapshotDiff { changes }
+    }
+}
+
+impl FromIterator<Entry> for MemorySnapshot {
+    fn from_iter<I: IntoIterator<Item = Entry>>(iter: I) -> Self {
+        let mut snapshot = Self::new();
+        for entry in iter {
+            snapshot.insert(entry);
+        }
+        snapshot
+    }
+}
+
+/// The differences between two [`MemorySnapshot`]s.
+///
+/// Its `Display` implementation renders a table for logs. For alerts, inspect
+/// [`SnapshotDiff::changes`] or, with the `serde` feature, serialize it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotDiff {
+    /// Sorted by the absolute change in bytes, largest first.
+    pub changes: Vec<Change>,
+}
+
+impl SnapshotDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The summed change of all collections, in bytes.
+    #[must_use]
+    pub fn delta(&self) -> i64 {
+        self.changes.iter().map(|change| change.delta).sum()
+    }
+}
+
+/// How a single collection changed between two snapshots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Change {
+    pub name: String,
+    pub kind: ChangeKind,
+    /// `None` if the collection appeared.
+    pub before: Option<Report>,
+    /// `None` if the collection disappeared.
+    pub after: Option<Report>,
+    /// Change of the total heap usage, in bytes.
+    pub delta: i64,
+}
+
+impl Change {
+    #[allow(clippy::cast_possible_wrap, reason = "Sizes are bounded by isize::MAX")]
+    fn new(name: &str, before: Option<Report>, after: Option<Report>) -> Option<Self> {
+        let total = |report: Option<Report>| report.map_or(0, |report| report.total() as i64);
+        let delta = total(after) - total(before);
+        let kind = match (before, after) {
+            (None, _) => ChangeKind::Appeared,
+            (_, None) => ChangeKind::Disappeared,
+            _ if delta > 0 => ChangeKind::Grew,
+            _ if delta < 0 => ChangeKind::Shrank,
+            _ => return None,
+        };
+        Some(Self {
+            name: name.to_owned(),
+            kind,
+            before,
+            after,
+            delta,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ChangeKind {
+    Appeared,
+    Disappeared,
+    Grew,
+    Shrank,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            ChangeKind::Appeared => "appeared",
+            ChangeKind::Disappeared => "disappeared",
+            ChangeKind::Grew => "grew",
+            ChangeKind::Shrank => "shrank",
+        })
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = |report: Option<Report>| report.map(|report| report.total().to_string());
+        let width = self
+            .changes
+            .iter()
+            .map(|change| change.name.len())
+            .chain(["collection".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:width$}  {:11}  {:>12}  {:>12}  {:>12}",
+            "collection", "change", "before", "after", "delta"
+        )?;
+        for change in &self.changes {
+            writeln!(
+                f,
+                "{:width$}  {:11}  {:>12}  {:>12}  {:>+12}",
+                change.name,
+                change.kind,
+                total(change.before).as_deref().unwrap_or("-"),
+                total(change.after).as_deref().unwrap_or("-"),
+                change.delta,
+            )?;
+        }
+        Ok(())
+    }
+}
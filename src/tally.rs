@@ -0,0 +1,625 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::Arc,
+};
+
+#[cfg(feature = "metrics")]
+use crate::gauge::Gauge;
+#[cfg(feature = "tracing")]
+use crate::trace::Trace;
+use crate::{
+    HeapSize, IndirectHeapSize, ShallowHeapSize, Tracked,
+    histogram::SizeHistogram,
+    largest::{KeyIndex, MapKey},
+    peak::Peak,
+    registry::Sizes,
+    sample::Estimate,
+    shared::SharedAllocation,
+    shrink::ShrinkPolicy,
+    tracked_value::IndexKey,
+};
+
+/// The bookkeeping of a [`Tracked`] collection. Every change in heap usage of
+/// the elements goes through here, so that the optional statistics stay in
+/// sync with the counters.
+///
+/// Public only because guards like [`crate::tracked_value::TrackedValue`]
+/// name it in their type; the module is private. It is generic over the
+/// collection `C` only for the index of the largest entries of maps.
+#[derive(Debug)]
+pub struct Tally<C> {
+    /// Heap memory allocated by the elements.
+    indirect: usize,
+    /// Part of `indirect` allocated by map keys. Always zero for other
+    /// collections.
+    keys: usize,
+    // Boxed, as most collections will not use any of it.
+    extras: Option<Box<Extras<C>>>,
+    #[cfg(feature = "tracing")]
+    trace: Trace,
+}
+
+/// The opt-in state of a [`Tally`].
+#[derive(Debug)]
+struct Extras<C> {
+    peak: Option<Peak>,
+    histogram: Option<SizeHistogram>,
+    /// Keys of a map by the heap size of their values.
+    largest: Option<Box<dyn KeyIndex<C>>>,
+    /// Whether `largest` missed changes and has to be rebuilt.
+    largest_stale: bool,
+    #[cfg(feature = "metrics")]
+    gauge: Option<Gauge>,
+    registration: Option<Arc<Sizes>>,
+    /// Set while the counters are an estimate, see `Tracked::from_sampled`.
+    estimate: Option<Estimate>,
+    shrink_policy: Option<ShrinkPolicy>,
+    /// The allocations of `SharedOnce` elements by address, with the number
+    /// of handles and the size they were counted with.
+    shared: HashMap<usize, (usize, usize)>,
+}
+
+/// The heap size of one element, and the allocation it shares with other
+/// elements, if any, which is counted once per collection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElementSize {
+    pub(crate) size: usize,
+    shared: Option<SharedAllocation>,
+}
+
+impl ElementSize {
+    pub(crate) fn of<T: HeapSize + ?Sized>(element: &T) -> Self {
+        Self {
+            size: element.heap_size(),
+            shared: element.shared_allocation(),
+        }
+    }
+}
+
+// Not derived, which would require `C: Default`.
+impl<C> Default for Tally<C> {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+impl<C> Default for Extras<C> {
+    fn default() -> Self {
+        Self {
+            peak: None,
+            histogram: None,
+            largest: None,
+            largest_stale: false,
+            #[cfg(feature = "metrics")]
+            gauge: None,
+            registration: None,
+            estimate: None,
+            shrink_policy: None,
+            shared: HashMap::new(),
+        }
+    }
+}
+
+impl<C> Tally<C> {
+    pub(crate) fn new(indirect: usize, keys: usize) -> Self {
+        Self {
+            indirect,
+            keys,
+            extras: None,
+            #[cfg(feature = "tracing")]
+            trace: Trace::new(indirect),
+        }
+    }
+
+    fn extras(&self) -> Option<&Extras<C>> {
+        self.extras.as_deref()
+    }
+
+    /// The opt-in state for setting `value`, allocated on first use, unless
+    /// `value` is `None` anyway.
+    fn extras_for<T>(&mut self, value: &Option<T>) -> Option<&mut Extras<C>> {
+        if value.is_none() && self.extras.is_none() {
+            return None;
+        }
+        Some(self.extras.get_or_insert_default())
+    }
+
+    fn histogram_mut(&mut self) -> Option<&mut SizeHistogram> {
+        self.extras.as_mut()?.histogram.as_mut()
+    }
+
+    pub(crate) fn indirect(&self) -> usize {
+        self.indirect
+    }
+
+    pub(crate) fn keys(&self) -> usize {
+        self.keys
+    }
+
+    /// An element of heap size `size` was added.
+    pub(crate) fn add(&mut self, size: ElementSize) {
+        self.add_many(size, 1);
+    }
+
+    /// `n` elements of heap size `size` each were added.
+    pub(crate) fn add_many(&mut self, size: ElementSize, n: usize) {
+        self.indirect += size.size * n;
+        if let Some(histogram) = self.histogram_mut() {
+            histogram.insert_many(size.size, n);
+        }
+        if let Some(shared) = size.shared
+            && n > 0
+        {
+            self.share(shared, n);
+        }
+    }
+
+    /// `n` more handles to `allocation` were added. Only the first one counts
+    /// it.
+    fn share(&mut self, allocation: SharedAllocation, n: usize) {
+        let extras = self.extras.get_or_insert_default();
+        match extras.shared.entry(allocation.addr) {
+            Entry::Occupied(mut o) => o.get_mut().0 += n,
+            Entry::Vacant(v) => {
+                v.insert((n, allocation.size));
+                self.indirect += allocation.size;
+            }
+        }
+    }
+
+    /// A handle to `allocation` was removed. The last one takes it out of
+    /// the count. Estimated tallies may not know it, see
+    /// `Tracked::from_sampled`.
+    fn unshare(&mut self, allocation: SharedAllocation) {
+        let Some(extras) = &mut self.extras else {
+            return;
+        };
+        let Entry::Occupied(mut o) = extras.shared.entry(allocation.addr) else {
+            return;
+        };
+        o.get_mut().0 -= 1;
+        if o.get().0 == 0 {
+            let (_, size) = o.remove();
+            let mut indirect = self.indirect;
+            self.subtract(&mut indirect, size);
+            self.indirect = indirect;
+        }
+    }
+
+    /// Subtracts `size` from `counter`. While the counters are an estimate,
+    /// they may be too low, so they stop at zero instead of underflowing.
+    fn subtract(&self, counter: &mut usize, size: usize) {
+        *counter = match self.estimate() {
+            Some(_) => counter.saturating_sub(size),
+            None => *counter - size,
+        };
+    }
+
+    /// An element of heap size `size` was removed.
+    pub(crate) fn remove(&mut self, size: ElementSize) {
+        let mut indirect = self.indirect;
+        self.subtract(&mut indirect, size.size);
+        self.indirect = indirect;
+        if let Some(histogram) = self.histogram_mut() {
+            histogram.remove(size.size);
+        }
+        if let Some(shared) = size.shared {
+            self.unshare(shared);
+        }
+    }
+
+    /// An element changed its heap size from `old` to `new`, or was replaced
+    /// by one of a different size.
+    pub(crate) fn resize(&mut self, old: ElementSize, new: ElementSize) {
+        // Add first, so that an allocation that the element still shares is
+        // not dropped from the count in between.
+        self.add(new);
+        self.remove(old);
+    }
+
+    /// All elements were removed.
+    pub(crate) fn clear(&mut self) {
+        self.indirect = 0;
+        self.keys = 0;
+        if let Some(extras) = &mut self.extras {
+            extras.estimate = None;
+            extras.shared.clear();
+            if let Some(histogram) = &mut extras.histogram {
+                histogram.clear();
+            }
+            if let Some(index) = &mut extras.largest {
+                index.clear();
+                extras.largest_stale = false;
+            }
+        }
+    }
+
+    /// The counters were recomputed from scratch. `sizes` yields the heap
+    /// size of every element, and of every value for maps, and `shared` the
+    /// allocation of every `SharedOnce` handle among them.
+    pub(crate) fn reset(
+        &mut self,
+        sizes: impl Iterator<Item = usize>,
+        keys: usize,
+        shared: impl Iterator<Item = SharedAllocation>,
+    ) {
+        match self.histogram_mut() {
+            Some(histogram) => {
+                histogram.clear();
+                let values: usize = sizes.inspect(|&size| histogram.insert_many(size, 1)).sum();
+                self.indirect = values;
+            }
+            None => self.indirect = sizes.sum(),
+        }
+        self.indirect += keys;
+        self.keys = keys;
+        self.set_estimate(None);
+        self.invalidate_largest();
+        self.reset_shared(shared);
+    }
+
+    /// Rebuilds the count of shared allocations from scratch, with their
+    /// sizes added to the counters.
+    fn reset_shared(&mut self, shared: impl Iterator<Item = SharedAllocation>) {
+        if let Some(extras) = &mut self.extras {
+            extras.shared.clear();
+        }
+        for allocation in shared {
+            self.share(allocation, 1);
+        }
+    }
+
+    /// Restores the count of shared allocations for counters that include
+    /// them already, e.g. from `Tracked::into_parts`.
+    pub(crate) fn restore_shared(&mut self, shared: impl Iterator<Item = SharedAllocation>) {
+        let indirect = self.indirect;
+        self.reset_shared(shared);
+        self.indirect = indirect;
+    }
+
+    /// A tally with the same counters, histogram and shrink policy, for a
+    /// clone whose elements allocate the same.
+    pub(crate) fn clone_counters(&self) -> Self {
+        let mut tally = Self::new(self.indirect, self.keys);
+        if let Some(extras) = self.extras()
+            && !extras.shared.is_empty()
+        {
+            tally.extras.get_or_insert_default().shared = extras.shared.clone();
+        }
+        tally.set_histogram(self.histogram().cloned());
+        tally.set_shrink_policy(self.shrink_policy());
+        tally
+    }
+
+    /// The same tally, for the collection converted to `U`. Only maps have
+    /// an index of the largest entries, and they are not converted, so it is
+    /// dropped.
+    pub(crate) fn convert<U>(self) -> Tally<U> {
+        Tally {
+            indirect: self.indirect,
+            keys: self.keys,
+            extras: self.extras.map(|extras| {
+                Box::new(Extras {
+                    peak: extras.peak,
+                    histogram: extras.histogram,
+                    largest: None,
+                    largest_stale: false,
+                    #[cfg(feature = "metrics")]
+                    gauge: extras.gauge,
+                    registration: extras.registration,
+                    estimate: extras.estimate,
+                    shrink_policy: extras.shrink_policy,
+                    shared: extras.shared,
+                })
+            }),
+            #[cfg(feature = "tracing")]
+            trace: self.trace,
+        }
+    }
+
+    /// Like [`Tally::reset`], but with the sum of the element sizes, and
+    /// their histogram if enabled, counted by the caller.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn reset_counted(
+        &mut self,
+        values: usize,
+        keys: usize,
+        histogram: Option<SizeHistogram>,
+        shared: impl Iterator<Item = SharedAllocation>,
+    ) {
+        if let (Some(old), Some(new)) = (self.histogram_mut(), histogram) {
+            *old = new;
+        }
+        self.indirect = values + keys;
+        self.keys = keys;
+        self.set_estimate(None);
+        self.invalidate_largest();
+        self.reset_shared(shared);
+    }
+
+    /// Elements were added whose sizes sum up to `sum`, with the histogram
+    /// of their sizes if enabled, and the allocations that they share.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn add_counted(
+        &mut self,
+        sum: usize,
+        histogram: Option<&SizeHistogram>,
+        shared: &[SharedAllocation],
+    ) {
+        self.indirect += sum;
+        if let (Some(old), Some(new)) = (self.histogram_mut(), histogram) {
+            old.merge(new);
+        }
+        for &allocation in shared {
+            self.share(allocation, 1);
+        }
+    }
+
+    pub(crate) fn estimate(&self) -> Option<Estimate> {
+        self.extras()?.estimate
+    }
+
+    pub(crate) fn set_estimate(&mut self, estimate: Option<Estimate>) {
+        if let Some(extras) = self.extras_for(&estimate) {
+            extras.estimate = estimate;
+        }
+    }
+
+    pub(crate) fn shrink_policy(&self) -> Option<ShrinkPolicy> {
+        self.extras()?.shrink_policy
+    }
+
+    pub(crate) fn set_shrink_policy(&mut self, policy: Option<ShrinkPolicy>) {
+        if let Some(extras) = self.extras_for(&policy) {
+            extras.shrink_policy = policy;
+        }
+    }
+
+    pub(crate) fn peak(&self) -> Option<&Peak> {
+        self.extras()?.peak.as_ref()
+    }
+
+    pub(crate) fn set_peak(&mut self, peak: Option<Peak>) {
+        if let Some(extras) = self.extras_for(&peak) {
+            extras.peak = peak;
+        }
+    }
+
+    pub(crate) fn histogram(&self) -> Option<&SizeHistogram> {
+        self.extras()?.histogram.as_ref()
+    }
+
+    pub(crate) fn set_histogram(&mut self, histogram: Option<SizeHistogram>) {
+        if let Some(extras) = self.extras_for(&histogram) {
+            extras.histogram = histogram;
+        }
+    }
+
+    pub(crate) fn largest_enabled(&self) -> bool {
+        self.extras().is_some_and(|extras| extras.largest.is_some())
+    }
+
+    /// The index of the largest entries, if enabled and up to date.
+    pub(crate) fn largest(&self) -> Option<&dyn KeyIndex<C>> {
+        let extras = self.extras()?;
+        extras.largest.as_deref().filter(|_| !extras.largest_stale)
+    }
+
+    fn largest_mut(&mut self) -> Option<&mut (dyn KeyIndex<C> + 'static)> {
+        let extras = self.extras.as_mut()?;
+        extras
+            .largest
+            .as_deref_mut()
+            .filter(|_| !extras.largest_stale)
+    }
+
+    /// Sets the index of the largest entries. It starts out stale, so it is
+    /// filled by the next [`Tally::rebuild_largest`].
+    pub(crate) fn set_largest(&mut self, index: Option<Box<dyn KeyIndex<C>>>) {
+        if let Some(extras) = self.extras_for(&index) {
+            extras.largest = index;
+            extras.largest_stale = true;
+        }
+    }
+
+    /// An operation changed elements without telling which keys.
+    pub(crate) fn invalidate_largest(&mut self) {
+        if let Some(extras) = &mut self.extras {
+            extras.largest_stale = true;
+        }
+    }
+
+    /// Whether [`Tally::after_op`] looks at the shallow heap size.
+    pub(crate) fn observes_shallow(&self) -> bool {
+        self.extras.is_some() || cfg!(feature = "tracing")
+    }
+
+    /// Called at the end of every operation of the collection, with the
+    /// collection's shallow heap size at that point.
+    pub(crate) fn after_op(&mut self, op: &'static str, shallow: impl FnOnce() -> usize) {
+        if !self.observes_shallow() {
+            return;
+        }
+        let shallow = shallow();
+        #[cfg(feature = "tracing")]
+        self.trace.observe(op, Some(shallow), self.indirect);
+        let Some(extras) = &mut self.extras else {
+            return;
+        };
+        if let Some(peak) = &mut extras.peak {
+            peak.observe(op, shallow, self.indirect);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(gauge) = &mut extras.gauge {
+            gauge.observe(shallow, self.indirect);
+        }
+        if let Some(sizes) = &extras.registration {
+            sizes.set_shallow(shallow);
+            sizes.set_indirect(self.indirect);
+        }
+    }
+
+    /// Like [`Tally::after_op`], but for operations through guards, which
+    /// cannot change the shallow heap size.
+    pub(crate) fn after_guard_op(&mut self, op: &'static str) {
+        #[cfg(feature = "tracing")]
+        self.trace.observe(op, None, self.indirect);
+        let Some(extras) = &mut self.extras else {
+            return;
+        };
+        if let Some(peak) = &mut extras.peak {
+            peak.observe(op, peak.shallow(), self.indirect);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(gauge) = &mut extras.gauge {
+            gauge.observe(gauge.shallow(), self.indirect);
+        }
+        if let Some(sizes) = &extras.registration {
+            sizes.set_indirect(self.indirect);
+        }
+    }
+
+    pub(crate) fn set_registration(&mut self, sizes: Option<Arc<Sizes>>) {
+        if let Some(extras) = self.extras_for(&sizes) {
+            extras.registration = sizes;
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn gauge_mut(&mut self) -> Option<&mut Gauge> {
+        self.extras.as_mut()?.gauge.as_mut()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn set_gauge(&mut self, gauge: Option<Gauge>) {
+        if let Some(extras) = self.extras_for(&gauge) {
+            extras.gauge = gauge;
+        }
+    }
+}
+
+impl<C: MapKey> Tally<C> {
+    /// The entry for `key` was added to a map, and its key allocates
+    /// `key_size` and its value `value_size`.
+    pub(crate) fn add_entry(&mut self, key: &C::Key, key_size: usize, value_size: ElementSize) {
+        self.indirect += key_size;
+        self.keys += key_size;
+        self.add(value_size);
+        if let Some(index) = self.largest_mut() {
+            index.insert(key, value_size.size);
+        }
+    }
+
+    /// The entry for `key` was removed from a map, see [`Tally::add_entry`].
+    pub(crate) fn remove_entry(&mut self, key: &C::Key, key_size: usize, value_size: ElementSize) {
+        let (mut indirect, mut keys) = (self.indirect, self.keys);
+        self.subtract(&mut indirect, key_size);
+        self.subtract(&mut keys, key_size);
+        (self.indirect, self.keys) = (indirect, keys);
+        self.remove(value_size);
+        if let Some(index) = self.largest_mut() {
+            index.remove(key, value_size.size);
+        }
+    }
+
+    /// Like [`Tally::resize`], but for the value of `key` in a map. Without
+    /// a key, the index of the largest entries goes stale.
+    pub(crate) fn resize_value(
+        &mut self,
+        key: Option<&C::Key>,
+        old: ElementSize,
+        new: ElementSize,
+    ) {
+        self.resize(old, new);
+        if old.size != new.size
+            && let Some(index) = self.largest_mut()
+        {
+            match key {
+                Some(key) => {
+                    index.remove(key, old.size);
+                    index.insert(key, new.size);
+                }
+                None => self.invalidate_largest(),
+            }
+        }
+    }
+
+    /// Refills the index of the largest entries from `entries` if it is
+    /// stale.
+    pub(crate) fn rebuild_largest<'a, I>(&mut self, entries: impl FnOnce() -> I)
+    where
+        C::Key: 'a,
+        I: Iterator<Item = (&'a C::Key, usize)>,
+    {
+        if let Some(extras) = &mut self.extras
+            && let Some(index) = &mut extras.largest
+            && extras.largest_stale
+        {
+            index.clear();
+            for (key, size) in entries() {
+                index.insert(key, size);
+            }
+            extras.largest_stale = false;
+        }
+    }
+
+    /// A copy of `key` for a guard, if the index of the largest entries
+    /// needs it.
+    pub(crate) fn index_key_owned<'a>(&self, key: &C::Key) -> Option<IndexKey<'a, C::Key>> {
+        self.largest()
+            .map(|index| IndexKey::Owned(index.clone_key(key)))
+    }
+}
+
+impl<C: ShallowHeapSize> Tracked<C> {
+    /// Called at the end of every mutating operation.
+    pub(crate) fn after_op(&mut self, op: &'static str) {
+        let inner = &self.inner;
+        self.tally.after_op(op, || inner.shallow_heap_size());
+    }
+}
+
+impl<C: IndirectHeapSize> Tracked<C> {
+    /// Moves the tally of `other` over to `self`, for when all elements of
+    /// `other` were moved over.
+    pub(crate) fn absorb_tally(&mut self, other: &mut Self) {
+        if let Some(histogram) = self.tally.histogram_mut() {
+            match other.tally.histogram() {
+                Some(other) => histogram.merge(other),
+                None => histogram.merge(&other.inner.element_heap_sizes().collect()),
+            }
+        }
+        self.tally.indirect += other.tally.indirect;
+        self.tally.keys += other.tally.keys;
+        // Allocations that both count are counted once from now on.
+        if let Some(other) = &mut other.tally.extras
+            && !other.shared.is_empty()
+        {
+            let mut twice = 0;
+            let shared = &mut self.tally.extras.get_or_insert_default().shared;
+            for (addr, (handles, size)) in other.shared.drain() {
+                match shared.entry(addr) {
+                    Entry::Occupied(mut o) => {
+                        o.get_mut().0 += handles;
+                        twice += size;
+                    }
+                    Entry::Vacant(v) => {
+                        v.insert((handles, size));
+                    }
+                }
+            }
+            let mut indirect = self.tally.indirect;
+            self.tally.subtract(&mut indirect, twice);
+            self.tally.indirect = indirect;
+        }
+        // Both errors could point the same way, so the margins add up.
+        let estimate = match (self.tally.estimate(), other.tally.estimate()) {
+            (Some(a), Some(b)) => Some(Estimate {
+                margin: a.margin + b.margin,
+            }),
+            (a, b) => a.or(b),
+        };
+        self.tally.set_estimate(estimate);
+        other.tally.clear();
+    }
+}
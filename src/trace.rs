@@ -0,0 +1,118 @@
+//! Reporting of heap usage changes to [`tracing`].
+//!
+//! Every mutating operation of a [`Tracked`](crate::Tracked) collection that
+//! changes its heap usage emits a `TRACE` event with target `memtally` and the
+//! fields `op`, `delta` and `total`, in bytes. To sum up the changes within a
+//! span, enter it through [`MemoryDelta`].
+//!
+//! The heap usage of the collection itself is only known from its first
+//! operation on, so a reallocation in that operation is left out of `delta`.
+
+use std::cell::RefCell;
+
+use ::tracing::{Span, span::EnteredSpan};
+
+thread_local! {
+    /// Cumulative deltas of the entered [`MemoryDelta`]s, innermost last.
+    static DELTAS: RefCell<Vec<i64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Enters a span and, when dropped, records the summed change in heap usage
+/// of all collections mutated on this thread in the meantime into its field
+/// [`MemoryDelta::FIELD`].
+///
+/// ```
+/// use memtally::trace::MemoryDelta;
+/// use tracing::{field, info_span};
+///
+/// let _span = MemoryDelta::enter(info_span!("request", memory_delta = field::Empty));
+/// // Mutate collections here.
+/// ```
+///
+/// Nested spans each record the changes within them. Like
+/// [`Span::entered`], this only follows the current thread, so it must not be
+/// held across an `.await`.
+#[derive(Debug)]
+#[must_use = "the delta is recorded when the guard is dropped"]
+pub struct MemoryDelta {
+    span: EnteredSpan,
+    depth: usize,
+}
+
+impl MemoryDelta {
+    /// The field of the span that receives the delta, in bytes.
+    pub const FIELD: &'static str = "memory_delta";
+
+    pub fn enter(span: Span) -> Self {
+        let depth = DELTAS.with_borrow_mut(|deltas| {
+            deltas.push(0);
+            deltas.len()
+        });
+        Self {
+            span: span.entered(),
+            depth,
+        }
+    }
+}
+
+impl Drop for MemoryDelta {
+    fn drop(&mut self) {
+        let delta = DELTAS.with_borrow_mut(|deltas| {
+            debug_assert_eq!(
+                deltas.len(),
+                self.depth,
+                "MemoryDelta guards must be dropped in reverse order"
+            );
+            deltas.pop()
+        });
+        if let Some(delta) = delta {
+            self.span.record(Self::FIELD, delta);
+        }
+    }
+}
+
+/// What the last traced operation saw of a collection.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Trace {
+    /// Unknown until the first operation.
+    shallow: Option<usize>,
+    indirect: usize,
+}
+
+impl Trace {
+    pub(crate) fn new(indirect: usize) -> Self {
+        Self {
+            shallow: None,
+            indirect,
+        }
+    }
+
+    /// Reports an operation. `shallow` is `None` for operations that cannot
+    /// change it.
+    #[allow(clippy::cast_possible_wrap, reason = "Sizes are bounded by isize::MAX")]
+    pub(crate) fn observe(&mut self, op: &'static str, shallow: Option<usize>, indirect: usize) {
+        // Before the first operation, the shallow heap usage is unknown, so a
+        // change of it in that operation is not reported.
+        let shallow_before = self.shallow.or(shallow);
+        let shallow = shallow.or(self.shallow);
+        let delta = (shallow.unwrap_or(0) as i64 - shallow_before.unwrap_or(0) as i64)
+            + (indirect as i64 - self.indirect as i64);
+        self.shallow = shallow;
+        self.indirect = indirect;
+
+        if delta == 0 {
+            return;
+        }
+        DELTAS.with_borrow_mut(|deltas| {
+            for cumulative in deltas {
+                *cumulative += delta;
+            }
+        });
+        match shallow {
+            Some(shallow) => {
+                ::tracing::trace!(target: "memtally", op, delta, total = shallow + indirect);
+            }
+            None => ::tracing::trace!(target: "memtally", op, delta),
+        }
+    }
+}
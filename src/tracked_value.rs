@@ -1,43 +1,148 @@
-use crate::HeapSize;
+use std::cell::Cell;
 
-pub struct TrackedValue<'a, V>
+use crate::{
+    HeapSize,
+    largest::MapKey,
+    tally::{ElementSize, Tally},
+};
+
+pub struct TrackedValue<'a, V, T>
 where
     V: HeapSize,
+    T: GuardTally,
 {
-    mem_tracker: &'a mut usize,
+    tally: T,
     value: &'a mut V,
-    size_before: usize,
+    size_before: ElementSize,
+}
+
+/// The guard of a value of map `C`.
+pub(crate) type TrackedMapValue<'a, C, V> =
+    TrackedValue<'a, V, Keyed<'a, &'a mut Tally<C>, <C as MapKey>::Key>>;
+
+/// The guard of a value of map `C` while iterating, which shares the tally.
+pub(crate) type SharedMapValue<'a, C, V> =
+    TrackedValue<'a, V, Keyed<'a, &'a Cell<Tally<C>>, <C as MapKey>::Key>>;
+
+/// How a guard gets to the tally of its collection. Unique access keeps the
+/// guard [`Send`], shared access lets several guards be alive at once, e.g.
+/// when iterating.
+pub trait GuardTally {
+    type Collection;
+
+    fn update(&mut self, f: impl FnOnce(&mut Tally<Self::Collection>));
+
+    /// Records that the guarded value changed its heap size.
+    fn resize(&mut self, old: ElementSize, new: ElementSize) {
+        self.update(|tally| {
+            tally.resize(old, new);
+            tally.after_guard_op("TrackedValue");
+        });
+    }
+}
+
+impl<C> GuardTally for &mut Tally<C> {
+    type Collection = C;
+
+    fn update(&mut self, f: impl FnOnce(&mut Tally<C>)) {
+        f(self);
+    }
 }
 
-impl<'a, V> TrackedValue<'a, V>
+impl<C> GuardTally for &Cell<Tally<C>> {
+    type Collection = C;
+
+    fn update(&mut self, f: impl FnOnce(&mut Tally<C>)) {
+        let mut tally = self.take();
+        f(&mut tally);
+        self.set(tally);
+    }
+}
+
+/// The tally of a map, with the key of the guarded value while the index of
+/// the largest entries needs it.
+pub struct Keyed<'a, T, K> {
+    tally: T,
+    key: Option<IndexKey<'a, K>>,
+}
+
+impl<'a, T, K> Keyed<'a, T, K> {
+    pub(crate) fn new(tally: T, key: Option<IndexKey<'a, K>>) -> Self {
+        Self { tally, key }
+    }
+}
+
+impl<T, K> GuardTally for Keyed<'_, T, K>
+where
+    T: GuardTally,
+    T::Collection: MapKey<Key = K>,
+{
+    type Collection = T::Collection;
+
+    fn update(&mut self, f: impl FnOnce(&mut Tally<T::Collection>)) {
+        self.tally.update(f);
+    }
+
+    fn resize(&mut self, old: ElementSize, new: ElementSize) {
+        let key = self.key.as_ref().map(IndexKey::get);
+        self.tally.update(|tally| {
+            tally.resize_value(key, old, new);
+            tally.after_guard_op("TrackedValue");
+        });
+    }
+}
+
+/// The key of a map value, as the index of the largest entries needs it.
+pub(crate) enum IndexKey<'a, K> {
+    Borrowed(&'a K),
+    Owned(K),
+}
+
+impl<K> IndexKey<'_, K> {
+    fn get(&self) -> &K {
+        match self {
+            IndexKey::Borrowed(key) => key,
+            IndexKey::Owned(key) => key,
+        }
+    }
+}
+
+impl<'a, V, T> TrackedValue<'a, V, T>
 where
     V: HeapSize,
+    T: GuardTally,
 {
-    pub(crate) fn new(tracker: &'a mut usize, value: &'a mut V) -> Self {
-        let size_before = V::heap_size(&*value);
+    pub(crate) fn new(tally: T, value: &'a mut V) -> Self {
+        let size_before = ElementSize::of(&*value);
+        Self::with_size(tally, value, size_before)
+    }
+
+    /// Like [`TrackedValue::new`], but for callers that already know the
+    /// current heap size of `value`.
+    pub(crate) fn with_size(tally: T, value: &'a mut V, size_before: ElementSize) -> Self {
         Self {
-            mem_tracker: tracker,
+            tally,
             value,
             size_before,
         }
     }
 }
 
-impl<V> Drop for TrackedValue<'_, V>
+impl<V, T> Drop for TrackedValue<'_, V, T>
 where
     V: HeapSize,
+    T: GuardTally,
 {
     fn drop(&mut self) {
-        let size_after = V::heap_size(self.value);
-
-        *self.mem_tracker -= self.size_before;
-        *self.mem_tracker += size_after;
+        let size_after = ElementSize::of(self.value);
+        self.tally.resize(self.size_before, size_after);
     }
 }
 
-impl<V> std::ops::Deref for TrackedValue<'_, V>
+impl<V, T> std::ops::Deref for TrackedValue<'_, V, T>
 where
     V: HeapSize,
+    T: GuardTally,
 {
     type Target = V;
     fn deref(&self) -> &Self::Target {
@@ -45,11 +150,46 @@ where
     }
 }
 
-impl<V> std::ops::DerefMut for TrackedValue<'_, V>
+impl<V, T> std::ops::DerefMut for TrackedValue<'_, V, T>
 where
     V: HeapSize,
+    T: GuardTally,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.value
     }
 }
+
+/// Records the changes in heap size of many values, without sampling the
+/// usage after each one like [`TrackedValue`] does.
+pub(crate) struct TrackedBatch<'a, C> {
+    tally: &'a mut Tally<C>,
+}
+
+impl<'a, C> TrackedBatch<'a, C> {
+    pub(crate) fn new(tally: &'a mut Tally<C>) -> Self {
+        Self { tally }
+    }
+
+    /// Runs `f` on `value` and records how its heap size changed.
+    pub(crate) fn update<V: HeapSize>(&mut self, value: &mut V, f: impl FnOnce(&mut V)) {
+        let size_before = ElementSize::of(value);
+        f(value);
+        self.tally.resize(size_before, ElementSize::of(value));
+    }
+
+    /// Like [`TrackedBatch::update`], but for the value of `key` in a map.
+    pub(crate) fn update_value<V: HeapSize>(
+        &mut self,
+        key: &C::Key,
+        value: &mut V,
+        f: impl FnOnce(&mut V),
+    ) where
+        C: MapKey,
+    {
+        let size_before = ElementSize::of(value);
+        f(value);
+        self.tally
+            .resize_value(Some(key), size_before, ElementSize::of(value));
+    }
+}
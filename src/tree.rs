@@ -0,0 +1,118 @@
+use std::fmt::{self, Write};
+
+use crate::{ShallowHeapSize, Tracked};
+
+/// A named tree of heap sizes, e.g. of a struct with one child per field and
+/// nested [`Tracked`] collections, as built by [`HeapTree`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryTree {
+    pub name: String,
+    /// Bytes allocated by this node itself, without its children.
+    pub size: usize,
+    pub children: Vec<MemoryTree>,
+}
+
+impl MemoryTree {
+    #[must_use]
+    pub fn leaf(name: impl Into<String>, size: usize) -> Self {
+        Self {
+            name: name.into(),
+            size,
+            children: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn node(name: impl Into<String>, children: Vec<MemoryTree>) -> Self {
+        Self {
+            name: name.into(),
+            size: 0,
+            children,
+        }
+    }
+
+    /// Bytes allocated by this node and all its descendants.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.size + self.children.iter().map(MemoryTree::total).sum::<usize>()
+    }
+
+    /// Writes the tree in the folded stack format of flamegraph tools, one
+    /// `root;child;leaf bytes` line per node that allocates anything itself.
+    ///
+    /// # Errors
+    ///
+    /// If writing to `out` fails.
+    pub fn write_folded(&self, out: &mut impl Write) -> fmt::Result {
+        self.write_folded_under(out, &mut String::new())
+    }
+
+    fn write_folded_under(&self, out: &mut impl Write, path: &mut String) -> fmt::Result {
+        let len = path.len();
+        if !path.is_empty() {
+            path.push(';');
+        }
+        // Semicolons separate frames in the folded format.
+        path.extend(self.name.chars().map(|c| if c == ';' { '_' } else { c }));
+        if self.size > 0 {
+            writeln!(out, "{path} {}", self.size)?;
+        }
+        for child in &self.children {
+            child.write_folded_under(out, path)?;
+        }
+        path.truncate(len);
+        Ok(())
+    }
+
+    /// The tree in the folded stack format, see [`MemoryTree::write_folded`].
+    #[must_use]
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        self.write_folded(&mut out)
+            .expect("writing to a String does not fail");
+        out
+    }
+}
+
+/// Types that can break down their heap usage into a [`MemoryTree`].
+///
+/// With the `derive` feature, `#[derive(HeapTree)]` implements it for structs
+/// with one child per field. Fields must implement `HeapTree` themselves,
+/// unless marked with `#[heap_tree(heap_size)]` to become a leaf sized by
+/// [`HeapSize`](crate::HeapSize), or with `#[heap_tree(skip)]`.
+pub trait HeapTree {
+    /// The tree of `self`, with `name` as the name of its root.
+    #[must_use]
+    fn heap_tree(&self, name: &str) -> MemoryTree;
+}
+
+impl<C: ShallowHeapSize> HeapTree for Tracked<C> {
+    /// A node with the leaves `shallow` and `indirect`.
+    fn heap_tree(&self, name: &str) -> MemoryTree {
+        MemoryTree::node(
+            name,
+            vec![
+                MemoryTree::leaf("shallow", self.inner.shallow_heap_size()),
+                MemoryTree::leaf("indirect", self.tally.indirect()),
+            ],
+        )
+    }
+}
+
+impl<T: HeapTree> HeapTree for Box<T> {
+    fn heap_tree(&self, name: &str) -> MemoryTree {
+        let mut tree = T::heap_tree(self, name);
+        tree.size += size_of::<T>();
+        tree
+    }
+}
+
+impl<T: HeapTree> HeapTree for Option<T> {
+    fn heap_tree(&self, name: &str) -> MemoryTree {
+        match self {
+            Some(value) => value.heap_tree(name),
+            None => MemoryTree::node(name, Vec::new()),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use memtally::{CloneHeapExact, HeapSize, ShrinkPolicy, Tracked, TrackedCollection};
+
+/// Clones of boxed slices allocate exactly as much as the original.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Blob(Box<[u8]>);
+
+impl HeapSize for Blob {
+    fn heap_size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl CloneHeapExact for Blob {}
+
+fn blobs(n: usize) -> impl Iterator<Item = Blob> {
+    (0..n).map(|i| Blob(vec![0; i].into_boxed_slice()))
+}
+
+#[test]
+fn clone_exact_copies_the_tally() {
+    let vec = Tracked::from_sampled(blobs(100).collect::<Vec<_>>(), 10);
+    assert!(vec.confidence_interval().is_some());
+
+    // An estimate carries over, where a recount makes it exact.
+    let exact = vec.clone_exact();
+    assert_eq!(exact.indirect_size(), vec.indirect_size());
+    assert_eq!(exact.confidence_interval(), vec.confidence_interval());
+
+    let recounted = vec.clone();
+    assert_eq!(recounted.indirect_size(), (0..100).sum::<usize>());
+    assert_eq!(recounted.confidence_interval(), None);
+}
+
+#[test]
+fn clones_keep_opt_in_state() {
+    let mut vec: Tracked<Vec<Blob>> = blobs(20).collect();
+    vec.enable_size_histogram();
+    vec.enable_peak_tracking();
+    vec.set_shrink_policy(Some(ShrinkPolicy::new(0.5)));
+
+    for clone in [vec.clone_exact(), vec.clone()] {
+        assert_eq!(clone.inner(), vec.inner());
+        assert_eq!(clone.indirect_size(), vec.indirect_size());
+        assert_eq!(clone.size_histogram(), vec.size_histogram());
+        assert_eq!(clone.shrink_policy(), vec.shrink_policy());
+        assert_eq!(clone.peak(), None);
+    }
+}
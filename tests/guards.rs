@@ -0,0 +1,56 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use memtally::{HeapSize, Tracked, TrackedCollection};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+fn key(i: u8) -> Name {
+    Name(i.to_string())
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+fn single_guards_are_send() {
+    let mut vec: Tracked<Vec<Name>> = Tracked::default();
+    vec.push(Name("a".repeat(10)));
+    let value = vec.get_mut(0).unwrap();
+    assert_send(&value);
+    drop(value);
+
+    let mut deque: Tracked<VecDeque<Name>> = Tracked::default();
+    deque.push_back(Name("a".repeat(10)));
+    let mut value = deque.get_mut(0).unwrap();
+    value.0.push_str(&"b".repeat(100));
+    assert_send(&value);
+    drop(value);
+    assert_eq!(deque.indirect_size(), deque.inner()[0].0.capacity());
+}
+
+#[test]
+fn map_guards_are_send() {
+    let mut map: Tracked<HashMap<Name, Name>> = Tracked::default();
+    map.enable_largest_index();
+    map.insert(key(1), Name("a".repeat(10)));
+    let mut value = map.get_mut(&key(1)).unwrap();
+    value.0.push_str(&"b".repeat(100));
+    assert_send(&value);
+    drop(value);
+    let value = map.entry(key(2)).or_insert(Name(String::new()));
+    assert_send(&value);
+    drop(value);
+    assert_eq!(map.largest(1), [&key(1)]);
+
+    let mut map: Tracked<BTreeMap<Name, Name>> = Tracked::default();
+    map.enable_largest_index();
+    map.insert(key(1), Name("a".repeat(10)));
+    let value = map.entry(key(1)).or_insert(Name(String::new()));
+    assert_send(&value);
+}
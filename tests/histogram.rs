@@ -0,0 +1,57 @@
+use memtally::{HeapSize, IndirectHeapSize, SizeHistogram, Tracked};
+
+#[derive(Clone, Debug)]
+struct Blob(Vec<u8>);
+
+impl HeapSize for Blob {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+fn blob(size: usize) -> Blob {
+    Blob(Vec::with_capacity(size))
+}
+
+/// Asserts that the histogram of `vec` matches a recount.
+fn assert_recounted(vec: &Tracked<Vec<Blob>>) {
+    let recount: SizeHistogram = vec.inner().element_heap_sizes().collect();
+    assert_eq!(vec.size_histogram(), Some(&recount));
+}
+
+#[test]
+fn percentiles() {
+    let histogram: SizeHistogram = [0, 1, 3, 3, 100].into_iter().collect();
+    assert_eq!(histogram.len(), 5);
+    assert_eq!(histogram.percentile(0.0), Some(0));
+    assert_eq!(histogram.percentile(40.0), Some(1));
+    assert_eq!(histogram.median(), Some(3));
+    assert_eq!(histogram.percentile(80.0), Some(3));
+    assert_eq!(histogram.percentile(100.0), Some(127));
+    assert_eq!(SizeHistogram::from_iter([]).median(), None);
+}
+
+#[test]
+fn follows_mutations() {
+    let mut vec: Tracked<Vec<Blob>> = (0..10).map(blob).collect();
+    assert_eq!(vec.size_histogram(), None);
+    vec.enable_size_histogram();
+    assert_recounted(&vec);
+
+    vec.push(blob(1000));
+    vec.get_mut(0).unwrap().0.reserve(64);
+    vec.swap_remove(3);
+    vec.retain(|blob| blob.0.capacity() != 5);
+    vec.truncate(6);
+    assert_recounted(&vec);
+    assert_eq!(vec.size_histogram().unwrap().percentile(100.0), Some(1023));
+
+    for mut value in vec.iter_mut_tracked() {
+        value.0 = Vec::new();
+    }
+    assert_recounted(&vec);
+    assert_eq!(vec.size_histogram().unwrap().percentile(100.0), Some(0));
+
+    vec.disable_size_histogram();
+    assert_eq!(vec.size_histogram(), None);
+}
@@ -0,0 +1,43 @@
+use memtally::{
+    MemorySnapshot,
+    registry::{Entry, Report},
+};
+
+#[test]
+fn snapshot_round_trips() {
+    let snapshot: MemorySnapshot = [("sessions", 1024, 4096), ("cache", 0, 0)]
+        .into_iter()
+        .map(|(name, shallow, indirect)| Entry {
+            name: name.to_owned(),
+            report: Report { shallow, indirect },
+        })
+        .collect();
+
+    let json = snapshot.to_json();
+    assert_eq!(MemorySnapshot::from_json(&json).unwrap(), snapshot);
+}
+
+#[test]
+fn reads_unsorted_snapshots() {
+    let json = r#"{"collections":[
+        {"name":"b","shallow":1,"indirect":2},
+        {"name":"a","shallow":3,"indirect":4},
+        {"name":"b","shallow":5,"indirect":6}
+    ]}"#;
+
+    let snapshot = MemorySnapshot::from_json(json).unwrap();
+    let names: Vec<&str> = snapshot
+        .collections()
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect();
+    assert_eq!(names, ["a", "b"]);
+    assert_eq!(
+        snapshot.get("b"),
+        Some(Report {
+            shallow: 6,
+            indirect: 8
+        })
+    );
+    assert!(MemorySnapshot::from_json(r#"{"collections":[{"name":"a"}]}"#).is_err());
+}
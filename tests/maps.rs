@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap};
+
+use memtally::{HeapSize, IndirectHeapSize, Tracked, TrackedCollection};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+fn name(s: &str) -> Name {
+    Name(s.to_owned())
+}
+
+/// Asserts that the tally of `map` matches a recount.
+macro_rules! assert_exact {
+    ($map:expr) => {{
+        let map = &$map;
+        assert_eq!(map.indirect_size(), map.inner().indirect_heap_size());
+        assert_eq!(map.key_heap_size(), map.inner().indirect_key_heap_size());
+        assert_eq!(
+            map.value_heap_size(),
+            map.inner().element_heap_sizes().sum::<usize>()
+        );
+    }};
+}
+
+#[test]
+fn hash_map_round_trip() {
+    let mut map: Tracked<HashMap<Name, Name>> = Tracked::default();
+    map.insert(name("a"), name("apple"));
+    map.insert(name("bb"), name("banana"));
+    map.insert(name("a"), name("avocado"));
+    assert_exact!(map);
+
+    map.entry(name("ccc")).or_insert(name("cherry"));
+    map.entry(name("a"))
+        .and_modify(|v| v.0.push_str(" pie"))
+        .or_default();
+    if let Some(mut value) = map.get_mut(&name("bb")) {
+        value.0 = String::new();
+    }
+    assert_exact!(map);
+
+    assert_eq!(map.remove(&name("a")), Some(name("avocado pie")));
+    assert_eq!(
+        map.remove_entry(&name("ccc")),
+        Some((name("ccc"), name("cherry")))
+    );
+    assert_exact!(map);
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn btree_map_round_trip() {
+    let mut map: Tracked<BTreeMap<Name, Name>> = Tracked::default();
+    map.insert(name("a"), name("apple"));
+    map.insert(name("bb"), name("banana"));
+    map.insert(name("a"), name("avocado"));
+    map.entry(name("ccc")).or_insert(name("cherry"));
+    map.entry(name("bb"))
+        .and_modify(|v| v.0.clear())
+        .or_default();
+    assert_exact!(map);
+
+    map.remove(&name("a"));
+    assert_exact!(map);
+    map.clear();
+    assert_exact!(map);
+    assert_eq!(map.indirect_size(), 0);
+}
+
+#[test]
+fn from_parts_keeps_key_split() {
+    let map = Tracked::from(HashMap::from([
+        (name("key"), name("value")),
+        (name("k"), name("v")),
+    ]));
+    let (inner, indirect) = map.into_parts();
+
+    // SAFETY: `indirect` is what `into_parts` returned.
+    let mut map = unsafe { Tracked::from_parts_unchecked(inner, indirect) };
+    assert_exact!(map);
+    map.remove(&name("key"));
+    map.remove(&name("k"));
+    assert_exact!(map);
+    assert_eq!(map.indirect_size(), 0);
+}
+
+#[test]
+fn from_parts_with_keys_round_trip() {
+    let map = Tracked::from(BTreeMap::from([(name("key"), name("value"))]));
+    let (inner, indirect, keys) = map.into_parts_with_keys();
+
+    // SAFETY: The sizes are what `into_parts_with_keys` returned.
+    let mut map =
+        unsafe { Tracked::<BTreeMap<_, _>>::from_parts_with_keys_unchecked(inner, indirect, keys) };
+    assert_exact!(map);
+    map.remove(&name("key"));
+    assert_eq!(map.indirect_size(), 0);
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Borrowed<'a>(&'a str);
+
+impl HeapSize for Borrowed<'_> {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+#[test]
+fn borrowed_keys() {
+    let text = String::from("alpha beta");
+    let mut words: Vec<Borrowed<'_>> = text.split(' ').map(Borrowed).collect();
+    let mut hash_map: Tracked<HashMap<Borrowed<'_>, Name>> = Tracked::default();
+    let mut btree_map: Tracked<BTreeMap<Borrowed<'_>, Name>> = Tracked::default();
+    for word in words.drain(..) {
+        hash_map.entry(Borrowed(word.0)).or_insert(name(word.0));
+        btree_map.insert(word, name("value"));
+    }
+    if let Some(mut value) = hash_map.get_mut(&Borrowed("beta")) {
+        value.0.push('!');
+    }
+    btree_map.remove(&Borrowed("alpha"));
+    assert_exact!(hash_map);
+    assert_exact!(btree_map);
+}
+
+#[test]
+fn entries_report_shallow_growth() {
+    let mut hash_map: Tracked<HashMap<Name, Name>> = Tracked::default();
+    let mut btree_map: Tracked<BTreeMap<Name, Name>> = Tracked::default();
+    hash_map.enable_peak_tracking();
+    btree_map.enable_peak_tracking();
+    for i in 0..10 {
+        let key = name(&i.to_string());
+        hash_map.entry(key.clone()).or_insert(name("value"));
+        btree_map.entry(key).or_insert(name("value"));
+    }
+    assert_eq!(
+        hash_map.peak().unwrap().total,
+        TrackedCollection::heap_size(&hash_map)
+    );
+    assert_eq!(
+        btree_map.peak().unwrap().total,
+        TrackedCollection::heap_size(&btree_map)
+    );
+}
+
+#[test]
+fn entries_of_present_keys_keep_capacity() {
+    for peak in [false, true] {
+        let mut map: Tracked<HashMap<Name, Name>> =
+            (0..3).map(|i| (name(&i.to_string()), name("v"))).collect();
+        if peak {
+            map.enable_peak_tracking();
+        }
+        map.shrink_to_fit();
+        let capacity = map.inner().capacity();
+        for _ in 0..10 {
+            map.entry(name("0")).or_default().0.push('x');
+        }
+        assert_eq!(map.inner().capacity(), capacity);
+        assert_exact!(map);
+    }
+}
+
+#[test]
+fn extend_replaces_values() {
+    let mut hash_map: Tracked<HashMap<Name, Name>> =
+        [(name("a"), name("apple")), (name("a"), name("avocado"))]
+            .into_iter()
+            .collect();
+    let mut btree_map: Tracked<BTreeMap<Name, Name>> =
+        hash_map.inner().clone().into_iter().collect();
+    assert_exact!(hash_map);
+    assert_exact!(btree_map);
+
+    let more = [(name("a"), name("apricot")), (name("bb"), name("banana"))];
+    hash_map.extend(more.clone());
+    btree_map.extend(more);
+    assert_exact!(hash_map);
+    assert_exact!(btree_map);
+
+    hash_map.remove(&name("a"));
+    btree_map.remove(&name("bb"));
+    assert_exact!(hash_map);
+    assert_exact!(btree_map);
+}
+
+#[test]
+fn sampled_removals_saturate() {
+    // Whenever the sample misses the large value, its removal takes the
+    // estimate below the keys.
+    for _ in 0..20 {
+        let map = HashMap::from([
+            (name("a key"), Name::default()),
+            (Name::default(), name(&"v".repeat(1000))),
+        ]);
+        let mut map = Tracked::from_sampled(map, 1);
+        map.remove(&Name::default());
+        assert!(map.value_heap_size() <= map.indirect_size());
+        map.with_inner_mut_unchanged(|_| ());
+        map.recalculate();
+        assert_exact!(map);
+    }
+}
+
+/// The keys of the `n` largest values of `map`, by scanning it.
+fn scan_largest<'a>(
+    map: impl IntoIterator<Item = (&'a Name, &'a Name)>,
+    n: usize,
+) -> Vec<&'a Name> {
+    let mut entries: Vec<_> = map.into_iter().collect();
+    entries.sort_by_key(|(_, v)| std::cmp::Reverse(Name::heap_size(v)));
+    entries.into_iter().take(n).map(|(k, _)| k).collect()
+}
+
+#[test]
+fn largest_index_follows_mutations() {
+    let mut map: Tracked<HashMap<Name, Name>> = (1..=10)
+        .map(|i| (name(&i.to_string()), Name("x".repeat(i * 10))))
+        .collect();
+    map.enable_largest_index();
+    assert_eq!(map.largest(3), scan_largest(map.inner(), 3));
+
+    map.insert(name("big"), Name("x".repeat(1000)));
+    map.remove(&name("10"));
+    map.entry(name("1"))
+        .and_modify(|v| v.0 = "x".repeat(500))
+        .or_default();
+    if let Some(mut value) = map.get_mut(&name("9")) {
+        value.0 = String::new();
+    }
+    assert_eq!(map.largest(3), [&name("big"), &name("1"), &name("8")]);
+    assert_eq!(map.largest(20), scan_largest(map.inner(), 20));
+
+    let mut btree: Tracked<BTreeMap<Name, Name>> = map.into_inner().into_iter().collect();
+    btree.enable_largest_index();
+    btree.remove(&name("big"));
+    btree
+        .entry(name("2"))
+        .or_default()
+        .0
+        .push_str(&"x".repeat(2000));
+    assert_eq!(btree.largest(2), [&name("2"), &name("1")]);
+    assert_eq!(btree.largest(20), scan_largest(btree.inner(), 20));
+}
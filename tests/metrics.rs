@@ -0,0 +1,113 @@
+use std::{collections::HashMap, time::Duration};
+
+use memtally::{HeapSize, Tracked, TrackedCollection};
+use metrics::with_local_recorder;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+fn name(s: &str) -> Name {
+    Name(s.to_owned())
+}
+
+/// The value and labels of every gauge called `name`.
+fn gauges(snapshotter: &Snapshotter, name: &str) -> Vec<(f64, Vec<(String, String)>)> {
+    snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .filter(|(key, ..)| key.key().name() == name)
+        .map(|(key, _, _, value)| {
+            let DebugValue::Gauge(value) = value else {
+                panic!("{name} is not a gauge");
+            };
+            let labels = key
+                .key()
+                .labels()
+                .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                .collect();
+            (value.0, labels)
+        })
+        .collect()
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn total(collection: &impl TrackedCollection) -> f64 {
+    (collection.shallow_size() + collection.indirect_size()) as f64
+}
+
+#[test]
+fn publishes_on_change() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let mut names: Tracked<Vec<Name>> = Tracked::default();
+
+    with_local_recorder(&recorder, || {
+        names.publish_gauge("names_bytes", &[("shard", "a")]);
+    });
+    assert_eq!(
+        gauges(&snapshotter, "names_bytes"),
+        [(0.0, vec![("shard".to_owned(), "a".to_owned())])]
+    );
+
+    names.push(name("first"));
+    assert_eq!(gauges(&snapshotter, "names_bytes")[0].0, total(&names));
+
+    names
+        .get_mut(0)
+        .unwrap()
+        .0
+        .push_str(" and a much longer suffix");
+    assert_eq!(gauges(&snapshotter, "names_bytes")[0].0, total(&names));
+
+    names.clear();
+    names.shrink_to_fit();
+    assert_eq!(gauges(&snapshotter, "names_bytes")[0].0, 0.0);
+}
+
+#[test]
+fn publishes_maps() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let mut sessions: Tracked<HashMap<Name, Name>> = Tracked::default();
+
+    with_local_recorder(&recorder, || sessions.publish_gauge("sessions_bytes", &[]));
+    sessions.insert(name("key"), name("value"));
+    assert_eq!(
+        gauges(&snapshotter, "sessions_bytes")[0].0,
+        total(&sessions)
+    );
+
+    sessions.remove(&name("key"));
+    assert_eq!(
+        gauges(&snapshotter, "sessions_bytes")[0].0,
+        total(&sessions)
+    );
+}
+
+#[test]
+fn throttles_until_flushed() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let mut names: Tracked<Vec<Name>> = Tracked::default();
+
+    with_local_recorder(&recorder, || {
+        names.publish_gauge_every("throttled_bytes", &[], Duration::from_secs(3600));
+    });
+    names.push(name("not yet published"));
+    assert_eq!(gauges(&snapshotter, "throttled_bytes")[0].0, 0.0);
+
+    names.flush_gauge();
+    assert_eq!(gauges(&snapshotter, "throttled_bytes")[0].0, total(&names));
+
+    names.unpublish_gauge();
+    names.push(name("not published anymore"));
+    assert_ne!(gauges(&snapshotter, "throttled_bytes")[0].0, total(&names));
+}
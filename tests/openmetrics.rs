@@ -0,0 +1,59 @@
+use std::collections::{BTreeMap, HashSet};
+
+use memtally::{HeapSize, Tracked, TrackedCollection, openmetrics::Encoder};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+#[test]
+fn renders_exposition() {
+    let mut map: Tracked<BTreeMap<Name, Name>> = Tracked::default();
+    map.insert(Name("a".to_owned()), Name("bcd".to_owned()));
+
+    let mut encoder = Encoder::with_prefix("app");
+    encoder.add_with_labels("users", &[("shard", "a\"b\\c\nd")], &map);
+
+    let labels = r#"{collection="users",shard="a\"b\\c\nd"}"#;
+    let shallow = map.shallow_size();
+    assert_eq!(
+        encoder.finish(),
+        format!(
+            "\
+# TYPE app_shallow_bytes gauge
+# UNIT app_shallow_bytes bytes
+# HELP app_shallow_bytes Heap memory allocated by the collection itself.
+app_shallow_bytes{labels} {shallow}
+# TYPE app_indirect_bytes gauge
+# UNIT app_indirect_bytes bytes
+# HELP app_indirect_bytes Heap memory allocated by the elements of the collection.
+app_indirect_bytes{labels} 4
+# TYPE app_len gauge
+# HELP app_len Number of elements in the collection.
+app_len{labels} 1
+# TYPE app_capacity gauge
+# HELP app_capacity Number of elements the collection can hold without reallocating.
+# EOF
+"
+        )
+    );
+}
+
+#[test]
+fn sanitizes_names() {
+    let set: Tracked<HashSet<Name>> = Tracked::default();
+
+    let mut encoder = Encoder::with_prefix("1my-app:mem");
+    encoder.add_with_labels("set", &[("some label", "x"), ("", "y")], &set);
+    let rendered = encoder.finish();
+
+    assert!(
+        rendered.contains("\n_1my_app:mem_len{collection=\"set\",some_label=\"x\",_=\"y\"} 0\n")
+    );
+    assert!(rendered.ends_with("\n# EOF\n"));
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use memtally::{HeapSize, IndirectHeapSize, Tracked, TrackedCollection, shared::SharedOnce};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+fn names(n: usize) -> Vec<Name> {
+    (0..n).map(|i| Name("x".repeat(i % 50))).collect()
+}
+
+fn entries(n: usize) -> Vec<(Name, Name)> {
+    names(n)
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (Name(i.to_string()), v))
+        .collect()
+}
+
+/// Asserts that `par_extend` counts like `extend`, including duplicates and
+/// replaced values.
+macro_rules! assert_par_extend {
+    ($ty:ty, $items:expr) => {{
+        let mut seq: Tracked<$ty> = $items.into_iter().take(10).collect();
+        let mut par: Tracked<$ty> = $items.into_iter().take(10).collect();
+        seq.extend($items);
+        par.par_extend($items);
+        assert_eq!(par.len(), seq.len());
+        assert_eq!(par.indirect_size(), seq.indirect_size());
+        par
+    }};
+    // Also checks the parallel recounts.
+    ($ty:ty, $items:expr, recount) => {{
+        let mut par = assert_par_extend!($ty, $items);
+        let indirect = par.indirect_size();
+        assert_eq!(par.par_clone().indirect_size(), indirect);
+        par.par_recalculate();
+        assert_eq!(par.indirect_size(), indirect);
+        assert_eq!(
+            Tracked::par_from(par.into_inner()).indirect_size(),
+            indirect
+        );
+    }};
+}
+
+#[test]
+fn par_extend_counts_like_extend() {
+    assert_par_extend!(Vec<Name>, names(1000), recount);
+    assert_par_extend!(VecDeque<Name>, names(1000), recount);
+    assert_par_extend!(BinaryHeap<Name>, names(1000));
+    assert_par_extend!(BTreeSet<Name>, names(1000), recount);
+    assert_par_extend!(HashSet<Name>, names(1000), recount);
+    assert_par_extend!(BTreeMap<Name, Name>, entries(1000), recount);
+    assert_par_extend!(HashMap<Name, Name>, entries(1000), recount);
+}
+
+#[test]
+fn par_extend_keeps_map_key_split() {
+    let mut map: Tracked<HashMap<Name, Name>> = Tracked::default();
+    map.par_extend(entries(100));
+    map.par_extend(
+        entries(100)
+            .into_iter()
+            .map(|(k, _)| (k, Name::default()))
+            .collect::<Vec<_>>(),
+    );
+    assert_eq!(map.value_heap_size(), 0);
+    assert_eq!(map.indirect_size(), map.key_heap_size());
+}
+
+#[test]
+fn par_recalculate_keeps_histogram() {
+    let mut vec: Tracked<Vec<Name>> = names(100).into_iter().collect();
+    vec.enable_size_histogram();
+    let histogram = vec.size_histogram().cloned();
+    vec.par_recalculate();
+    assert_eq!(vec.size_histogram().cloned(), histogram);
+}
+
+#[test]
+fn par_recount_counts_shared_allocations_once() {
+    let name = Arc::new(Name("x".repeat(100)));
+    let handles: Vec<_> = (0..1000).map(|_| SharedOnce(Arc::clone(&name))).collect();
+    let expected = handles.indirect_heap_size();
+    let mut handles = Tracked::par_from(handles);
+    assert_eq!(handles.indirect_size(), expected);
+    assert_eq!(handles.par_clone().indirect_size(), expected);
+    handles.par_recalculate();
+    assert_eq!(handles.indirect_size(), expected);
+    handles.truncate(1);
+    assert_eq!(handles.indirect_size(), expected);
+}
@@ -0,0 +1,77 @@
+use memtally::{
+    HeapSize, ShallowHeapSize, Tracked, TrackedCollection,
+    registry::{self, Entry, Report},
+};
+
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+// The registry is shared by all tests, which run in parallel, so each test
+// only looks at its own names.
+fn entries(name: &str) -> Vec<Entry> {
+    registry::snapshot()
+        .into_iter()
+        .filter(|entry| entry.name == name)
+        .collect()
+}
+
+fn report(tracked: &Tracked<Vec<Name>>) -> Report {
+    Report {
+        shallow: tracked.inner().shallow_heap_size(),
+        indirect: tracked.indirect_size(),
+    }
+}
+
+#[test]
+fn follows_operations() {
+    let mut names: Tracked<Vec<Name>> = Tracked::default();
+    names.register("follows_operations");
+    assert_eq!(entries("follows_operations")[0].report, Report::default());
+
+    names.push(Name("x".repeat(100)));
+    assert_eq!(entries("follows_operations")[0].report, report(&names));
+
+    if let Some(mut name) = names.get_mut(0) {
+        name.0.push_str(&"y".repeat(100));
+    }
+    let entries = entries("follows_operations");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].report, report(&names));
+    assert!(entries[0].report.total() >= 200);
+}
+
+#[test]
+fn register_again_renames() {
+    let mut names: Tracked<Vec<Name>> = Tracked::default();
+    names.register("register_again_renames_old");
+    names.register("register_again_renames_new");
+    assert!(entries("register_again_renames_old").is_empty());
+    assert_eq!(entries("register_again_renames_new").len(), 1);
+}
+
+#[test]
+fn unregistered_and_dropped_are_pruned() {
+    let mut kept: Tracked<Vec<Name>> = Tracked::default();
+    let mut left: Tracked<Vec<Name>> = Tracked::default();
+    let mut dropped: Tracked<Vec<Name>> = Tracked::default();
+    kept.register("pruned");
+    left.register("pruned");
+    dropped.register("pruned");
+    assert_eq!(entries("pruned").len(), 3);
+
+    left.unregister();
+    drop(dropped);
+    kept.push(Name("x".repeat(10)));
+    assert_eq!(
+        entries("pruned"),
+        [Entry {
+            name: "pruned".into(),
+            report: report(&kept),
+        }]
+    );
+}
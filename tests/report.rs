@@ -0,0 +1,145 @@
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+const BEFORE: &str = r#"{"collections":[
+    {"name":"cache","shallow":100,"indirect":400},
+    {"name":"queue","shallow":32,"indirect":0},
+    {"name":"sessions","shallow":64,"indirect":36}
+]}"#;
+
+const AFTER: &str = r#"{"collections":[
+    {"name":"cache","shallow":100,"indirect":350},
+    {"name":"sessions","shallow":164,"indirect":36},
+    {"name":"users;v2","shallow":8,"indirect":2}
+]}"#;
+
+/// Writes `json` to a file unique to the calling test.
+fn snapshot(test: &str, json: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{test}.json"));
+    fs::write(&path, json).unwrap();
+    path
+}
+
+fn report(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_memtally-report"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(args: &[&str], stdin: &str) -> String {
+    let output = report(args, stdin);
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn top() {
+    assert_eq!(
+        stdout(&["-"], BEFORE),
+        "\
+collection       shallow      indirect         total
+cache                100           400           500
+sessions              64            36           100
+queue                 32             0            32
+"
+    );
+    assert_eq!(
+        stdout(&["--sort", "shallow", "-n", "1", "-"], BEFORE),
+        "\
+collection       shallow      indirect         total
+cache                100           400           500
+(2 more)              96            36           132
+"
+    );
+}
+
+#[test]
+fn diff() {
+    let before = snapshot("diff_before", BEFORE);
+    let after = snapshot("diff_after", AFTER);
+    let before = before.to_str().unwrap();
+    let after = after.to_str().unwrap();
+
+    assert_eq!(
+        stdout(&[before, after], ""),
+        "\
+collection  change             before         after         delta
+sessions    grew                  100           200          +100
+cache       shrank                500           450           -50
+queue       disappeared            32             -           -32
+users;v2    appeared                -            10           +10
+total: 632 -> 660 bytes (+28)
+"
+    );
+    assert_eq!(
+        stdout(&["-s", "indirect", "--top", "2", before, after], ""),
+        "\
+collection  change             before         after         delta
+cache       shrank                500           450           -50
+users;v2    appeared                -            10           +10
+(2 more changes)
+total: 632 -> 660 bytes (+28)
+"
+    );
+}
+
+#[test]
+fn folded() {
+    assert_eq!(
+        stdout(&["--folded", "-"], AFTER),
+        "\
+cache;shallow 100
+cache;indirect 350
+sessions;shallow 164
+sessions;indirect 36
+users_v2;shallow 8
+users_v2;indirect 2
+"
+    );
+}
+
+#[test]
+fn help() {
+    assert!(stdout(&["-h"], "").starts_with("Usage: memtally-report"));
+}
+
+#[test]
+fn invalid_arguments() {
+    for (args, error) in [
+        (&[][..], "expected one or two snapshot files"),
+        (&["a", "b", "c"], "expected one or two snapshot files"),
+        (&["--folded", "a", "b"], "--folded takes a single snapshot"),
+        (&["--top"], "--top needs a value"),
+        (&["--top", "x", "a"], "invalid --top: x"),
+        (&["--sort", "name", "a"], "invalid --sort: name"),
+        (&["--verbose", "a"], "unknown option: --verbose"),
+    ] {
+        let output = report(args, "");
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with(&format!("error: {error}\n")), "{stderr}");
+    }
+
+    let output = report(&["-"], "{}");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("error: cannot parse -: ")
+    );
+}
@@ -0,0 +1,95 @@
+use std::collections::{BTreeSet, HashSet};
+
+use memtally::{HeapSize, IndirectHeapSize, ShrinkPolicy, Tracked, TrackedCollection};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+fn names(n: usize) -> impl Iterator<Item = Name> {
+    (0..n).map(|i| Name("x".repeat(i)))
+}
+
+#[test]
+fn hash_set_extract_if_shrinks() {
+    let mut set: Tracked<HashSet<Name>> = names(100).collect();
+    set.set_shrink_policy(Some(ShrinkPolicy::new(0.75)));
+    let capacity = set.capacity();
+
+    let extracted: Vec<Name> = set.extract_if(|name| name.0.len() >= 10).collect();
+    assert_eq!(extracted.len(), 90);
+    assert_eq!(set.indirect_size(), set.inner().indirect_heap_size());
+    // Removals leave tombstones that lower the capacity a bit, too.
+    assert!(set.capacity() < capacity / 4);
+}
+
+#[test]
+fn hash_set_extract_if_dropped_early() {
+    let mut set: Tracked<HashSet<Name>> = names(20).collect();
+
+    let first = set.extract_if(|name| name.0.len() >= 10).take(3).count();
+    assert_eq!(first, 3);
+    assert_eq!(set.len(), 17);
+    assert_eq!(set.indirect_size(), set.inner().indirect_heap_size());
+}
+
+#[test]
+fn btree_set_extract_if_dropped_early() {
+    let mut set: Tracked<BTreeSet<Name>> = names(20).collect();
+
+    let first = set.extract_if(.., |_| true).take(5).count();
+    assert_eq!(first, 5);
+    assert_eq!(set.len(), 15);
+    assert_eq!(set.indirect_size(), set.inner().indirect_heap_size());
+}
+
+#[test]
+fn extend_drops_duplicates() {
+    let mut set: Tracked<HashSet<Name>> = names(3).chain(names(3)).collect();
+    set.extend(names(5));
+    assert_eq!(set.len(), 5);
+    assert_eq!(set.indirect_size(), set.inner().indirect_heap_size());
+}
+
+#[test]
+fn get_or_insert_with_counts_new_values() {
+    let mut set: Tracked<HashSet<Name>> = names(3).collect();
+    let key = Name("x".repeat(50));
+    set.get_or_insert_with(&key, Clone::clone);
+    set.get_or_insert_with(&key, |_| unreachable!());
+    assert_eq!(set.len(), 4);
+    assert_eq!(set.indirect_size(), set.inner().indirect_heap_size());
+}
+
+#[test]
+#[should_panic = "new value is not equal to the key"]
+fn get_or_insert_with_checks_new_values() {
+    let mut set: Tracked<BTreeSet<Name>> = names(3).collect();
+    set.get_or_insert_with(&Name("a".into()), |_| Name("b".into()));
+}
+
+#[test]
+fn btree_set_append_tracked() {
+    let mut set: Tracked<BTreeSet<Name>> = names(5).collect();
+    let mut other: Tracked<BTreeSet<Name>> = names(10).collect();
+    set.append_tracked(&mut other);
+    assert_eq!(set.len(), 10);
+    assert_eq!(set.indirect_size(), set.inner().indirect_heap_size());
+    assert!(other.is_empty());
+    assert_eq!(other.indirect_size(), 0);
+}
+
+#[test]
+fn tracked_capacity_does_not_shadow_capacity() {
+    let hash_set: Tracked<HashSet<Name>> = names(10).collect();
+    let capacity: usize = hash_set.capacity();
+    assert_eq!(hash_set.tracked_capacity(), Some(capacity));
+
+    let btree_set: Tracked<BTreeSet<Name>> = names(10).collect();
+    assert_eq!(btree_set.tracked_capacity(), None);
+}
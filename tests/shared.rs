@@ -0,0 +1,108 @@
+use std::{collections::HashMap, sync::Arc};
+
+use memtally::{
+    HeapSize, IndirectHeapSize, Tracked, TrackedCollection,
+    shared::{SharedOnce, SharedSplit},
+};
+
+#[derive(PartialEq, Eq, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+#[test]
+fn split_share_is_kept() {
+    let name = Arc::new(Name("x".repeat(100)));
+    let mut handles: Tracked<Vec<SharedSplit<Arc<Name>>>> = Tracked::default();
+    handles.push(SharedSplit::new(Arc::clone(&name)));
+    handles.push(SharedSplit::new(Arc::clone(&name)));
+    let counted = handles.indirect_size();
+    assert!(counted > 0);
+
+    // Sharing changes behind the collection's back.
+    let more: Vec<_> = (0..5).map(|_| Arc::clone(&name)).collect();
+    assert_eq!(
+        handles.iter().map(HeapSize::heap_size).sum::<usize>(),
+        counted
+    );
+    drop(more);
+
+    handles.pop();
+    handles.pop();
+    assert_eq!(handles.indirect_size(), 0);
+}
+
+#[test]
+fn once_counts_every_allocation_once() {
+    let a = Arc::new(Name("a".repeat(100)));
+    let b = Arc::new(Name("b".repeat(50)));
+    let mut handles: Tracked<Vec<SharedOnce<Arc<Name>>>> = Tracked::default();
+    handles.push(SharedOnce(Arc::clone(&a)));
+    let one = handles.indirect_size();
+    assert!(one > 100);
+    handles.push(SharedOnce(Arc::clone(&a)));
+    assert_eq!(handles.indirect_size(), one);
+    handles.push(SharedOnce(Arc::clone(&b)));
+    assert_eq!(
+        handles.indirect_size(),
+        handles.inner().indirect_heap_size()
+    );
+
+    if let Some(mut last) = handles.get_mut(2) {
+        *last = SharedOnce(Arc::clone(&a));
+    }
+    assert_eq!(handles.indirect_size(), one);
+
+    let mut clone = handles.clone();
+    assert_eq!(clone.indirect_size(), one);
+    handles.append_tracked(&mut clone);
+    assert_eq!(handles.len(), 6);
+    assert_eq!(handles.indirect_size(), one);
+
+    handles.truncate(1);
+    assert_eq!(handles.indirect_size(), one);
+    handles.pop();
+    assert_eq!(handles.indirect_size(), 0);
+}
+
+#[test]
+fn once_collected() {
+    let a = Arc::new(Name("a".repeat(100)));
+    let mut handles: Tracked<Vec<SharedOnce<Arc<Name>>>> =
+        (0..3).map(|_| SharedOnce(Arc::clone(&a))).collect();
+    let one = handles.indirect_size();
+    assert!(one > 100);
+    assert_eq!(one, handles.inner().indirect_heap_size());
+
+    handles.truncate(1);
+    assert_eq!(handles.indirect_size(), one);
+    handles.pop();
+    assert_eq!(handles.indirect_size(), 0);
+}
+
+fn key(i: u8) -> Name {
+    Name(i.to_string())
+}
+
+#[test]
+fn once_in_map_values() {
+    let a = Arc::new(Name("a".repeat(100)));
+    let mut map: Tracked<HashMap<Name, SharedOnce<Arc<Name>>>> = Tracked::default();
+    map.insert(key(1), SharedOnce(Arc::clone(&a)));
+    map.insert(key(2), SharedOnce(Arc::clone(&a)));
+    map.entry(key(3)).or_insert(SharedOnce(Arc::clone(&a)));
+    assert_eq!(map.indirect_size(), map.inner().indirect_heap_size());
+
+    let (inner, indirect) = map.into_parts();
+    // SAFETY: `indirect` is what `into_parts` returned.
+    let mut map = unsafe { Tracked::from_parts_unchecked(inner, indirect) };
+    map.remove(&key(1));
+    map.remove(&key(2));
+    assert!(map.indirect_size() > 0);
+    map.remove(&key(3));
+    assert_eq!(map.indirect_size(), 0);
+}
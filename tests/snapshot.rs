@@ -0,0 +1,130 @@
+use memtally::{
+    Change, ChangeKind, MemorySnapshot,
+    registry::{Entry, Report},
+};
+
+fn entry(name: &str, shallow: usize, indirect: usize) -> Entry {
+    Entry {
+        name: name.to_owned(),
+        report: Report { shallow, indirect },
+    }
+}
+
+fn before() -> MemorySnapshot {
+    [
+        entry("cache", 100, 400),
+        entry("sessions", 64, 36),
+        entry("queue", 32, 0),
+        entry("stable", 10, 10),
+    ]
+    .into_iter()
+    .collect()
+}
+
+fn after() -> MemorySnapshot {
+    [
+        entry("cache", 100, 100),
+        entry("sessions", 64, 136),
+        entry("stable", 0, 20),
+        entry("users", 8, 2),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[test]
+fn same_names_are_summed() {
+    let snapshot: MemorySnapshot = [entry("b", 1, 2), entry("a", 3, 4), entry("b", 5, 6)]
+        .into_iter()
+        .collect();
+
+    let names: Vec<&str> = snapshot
+        .collections()
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect();
+    assert_eq!(names, ["a", "b"]);
+    assert_eq!(
+        snapshot.get("b"),
+        Some(Report {
+            shallow: 6,
+            indirect: 8
+        })
+    );
+    assert_eq!(snapshot.total(), 21);
+}
+
+#[test]
+fn diff_reports_changes_largest_first() {
+    let diff = before().diff(&after());
+
+    assert_eq!(
+        diff.changes,
+        [
+            Change {
+                name: "cache".to_owned(),
+                kind: ChangeKind::Shrank,
+                before: Some(Report {
+                    shallow: 100,
+                    indirect: 400
+                }),
+                after: Some(Report {
+                    shallow: 100,
+                    indirect: 100
+                }),
+                delta: -300,
+            },
+            Change {
+                name: "sessions".to_owned(),
+                kind: ChangeKind::Grew,
+                before: Some(Report {
+                    shallow: 64,
+                    indirect: 36
+                }),
+                after: Some(Report {
+                    shallow: 64,
+                    indirect: 136
+                }),
+                delta: 100,
+            },
+            Change {
+                name: "queue".to_owned(),
+                kind: ChangeKind::Disappeared,
+                before: Some(Report {
+                    shallow: 32,
+                    indirect: 0
+                }),
+                after: None,
+                delta: -32,
+            },
+            Change {
+                name: "users".to_owned(),
+                kind: ChangeKind::Appeared,
+                before: None,
+                after: Some(Report {
+                    shallow: 8,
+                    indirect: 2
+                }),
+                delta: 10,
+            },
+        ]
+    );
+    assert_eq!(diff.delta(), -222);
+    assert!(before().diff(&before()).is_empty());
+}
+
+#[test]
+fn diff_displays_table() {
+    let diff = before().diff(&after());
+
+    assert_eq!(
+        diff.to_string(),
+        "\
+collection  change             before         after         delta
+cache       shrank                500           200          -300
+sessions    grew                  100           200          +100
+queue       disappeared            32             -           -32
+users       appeared                -            10           +10
+"
+    );
+}
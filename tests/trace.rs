@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use memtally::{HeapSize, Tracked, trace::MemoryDelta};
+use tracing::{
+    Event, Id, Metadata, Subscriber,
+    field::{self, Field, Visit},
+    info_span,
+    span::{Attributes, Record},
+};
+
+#[derive(Debug)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+fn name(len: usize) -> Name {
+    Name("x".repeat(len))
+}
+
+/// Logs the events as `<op> <delta>` and the recorded deltas of spans as
+/// `<span> <delta>`.
+#[derive(Default)]
+struct Capture {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, &'static str>>,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+#[derive(Default)]
+struct Fields {
+    op: Option<String>,
+    delta: Option<i64>,
+}
+
+impl Visit for Fields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if matches!(field.name(), "delta" | MemoryDelta::FIELD) {
+            self.delta = Some(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "op" {
+            self.op = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.spans
+            .lock()
+            .unwrap()
+            .insert(id, span.metadata().name());
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        let name = self.spans.lock().unwrap()[&span.into_u64()];
+        let delta = fields.delta.unwrap();
+        self.log.lock().unwrap().push(format!("{name} {delta:+}"));
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let (op, delta) = (fields.op.unwrap(), fields.delta.unwrap());
+        self.log.lock().unwrap().push(format!("{op} {delta:+}"));
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn nested_spans_sum_up_their_deltas() {
+    let capture = Capture::default();
+    let log = Arc::clone(&capture.log);
+    let mut vec = Tracked::<Vec<Name>>::with_capacity(8);
+
+    tracing::subscriber::with_default(capture, || {
+        vec.push(name(3));
+        {
+            let _outer = MemoryDelta::enter(info_span!("outer", memory_delta = field::Empty));
+            vec.push(name(5));
+            {
+                let _inner = MemoryDelta::enter(info_span!("inner", memory_delta = field::Empty));
+                vec.pop();
+                vec.push(name(7));
+                // Changes nothing, so no event.
+                vec.retain(|_| true);
+            }
+            vec.push(name(1));
+        }
+        let _later = MemoryDelta::enter(info_span!("later", memory_delta = field::Empty));
+        vec.retain(|_| true);
+    });
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "push +3", "push +5", "pop -5", "push +7", "inner +2", "push +1", "outer +8",
+            "later +0",
+        ]
+    );
+}
@@ -0,0 +1,64 @@
+use std::collections::{HashMap, HashSet};
+
+use memtally::{HeapSize, HeapTree, MemoryTree, Tracked, TrackedCollection};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Name(String);
+
+impl HeapSize for Name {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+#[derive(HeapTree)]
+struct Index(Tracked<HashSet<Name>>);
+
+#[derive(HeapTree)]
+struct Store {
+    users: Tracked<HashMap<Name, Name>>,
+    index: Box<Index>,
+    #[heap_tree(heap_size)]
+    label: Name,
+    #[heap_tree(skip)]
+    #[allow(dead_code, reason = "Only there to be skipped")]
+    cache: Name,
+}
+
+fn collection(name: &str, tracked: &impl TrackedCollection) -> MemoryTree {
+    MemoryTree::node(
+        name,
+        vec![
+            MemoryTree::leaf("shallow", tracked.shallow_size()),
+            MemoryTree::leaf("indirect", tracked.indirect_size()),
+        ],
+    )
+}
+
+#[test]
+fn derived_tree() {
+    let mut users: Tracked<HashMap<Name, Name>> = Tracked::default();
+    users.insert(Name("ada".to_owned()), Name("Ada Lovelace".to_owned()));
+    let index: Tracked<HashSet<Name>> = [Name("ada".to_owned())].into_iter().collect();
+    let store = Store {
+        users,
+        index: Box::new(Index(index)),
+        label: Name("store".to_owned()),
+        cache: Name("ignored".to_owned()),
+    };
+
+    let mut index = MemoryTree::node("index", vec![collection("0", &store.index.0)]);
+    index.size = size_of::<Index>();
+    let expected = MemoryTree::node(
+        "store",
+        vec![
+            collection("users", &store.users),
+            index,
+            MemoryTree::leaf("label", 5),
+        ],
+    );
+    let tree = store.heap_tree("store");
+    assert_eq!(tree, expected);
+    assert!(tree.folded().contains("store;index;0;indirect "));
+    assert!(!tree.folded().contains("cache"));
+}